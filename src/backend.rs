@@ -0,0 +1,173 @@
+//! Storage backends the [`crate::Server`] uploads to and downloads from.
+//!
+//! [`AutonomiBackend`] talks to the real network, [`MemoryBackend`] keeps everything in memory
+//! so the upload flow can be exercised offline.
+
+mod autonomi;
+mod memory;
+
+pub use self::autonomi::{AutonomiBackend, DEFAULT_LOCAL_SECRET_KEY};
+pub use self::memory::MemoryBackend;
+
+use ::autonomi::client::ChunkBatchUploadState;
+use ::autonomi::client::payment::Receipt;
+use ::autonomi::data::DataAddress;
+use ::autonomi::{AttoTokens, Bytes, Chunk, XorName};
+
+/// Error returned when storing data or chunks
+#[derive(Debug)]
+pub enum PutError {
+    /// Some chunks failed to upload after paying, the payment in the state can be reused
+    Batch(ChunkBatchUploadState),
+    Other(String),
+}
+
+impl std::fmt::Display for PutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PutError::Batch(upload_state) => write!(f, "{upload_state}"),
+            PutError::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Cost of storing chunks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quote {
+    /// Tokens paid to the nodes storing the chunks
+    pub tokens: AttoTokens,
+    /// Number of chunks not stored on the network yet, paid for even if they cost no tokens
+    pub paid_chunks: usize,
+    /// Rough estimate of the gas fees of the payment transactions in wei, at a fixed gas price
+    pub gas: u128,
+    /// Part of the gas paid once per payment, saved by paying for several uploads at once
+    pub transaction_gas: u128,
+}
+
+impl Quote {
+    /// Tokens to pay in ANT
+    pub fn ant(&self) -> f64 {
+        whole_tokens(&self.tokens)
+    }
+
+    /// Estimated gas fees in ETH
+    pub fn eth(&self) -> f64 {
+        self.gas as f64 / 1e18
+    }
+
+    /// Cost of storing both sets of chunks with a single payment
+    pub fn batched_with(self, other: Quote) -> Quote {
+        let shared_gas = self.transaction_gas.min(other.transaction_gas);
+        let separately = self + other;
+        Quote {
+            tokens: separately.tokens,
+            paid_chunks: separately.paid_chunks,
+            gas: separately.gas.saturating_sub(shared_gas),
+            transaction_gas: self.transaction_gas.max(other.transaction_gas),
+        }
+    }
+}
+
+impl std::ops::Add for Quote {
+    type Output = Quote;
+
+    /// Cost of storing both sets of chunks, paying for them separately
+    fn add(self, other: Quote) -> Quote {
+        Quote {
+            tokens: self.tokens.checked_add(other.tokens).unwrap_or(self.tokens),
+            paid_chunks: self.paid_chunks.saturating_add(other.paid_chunks),
+            gas: self.gas.saturating_add(other.gas),
+            transaction_gas: self.transaction_gas.saturating_add(other.transaction_gas),
+        }
+    }
+}
+
+/// Error refusing a payment whose price rose above the one approved
+fn price_rose(price: AttoTokens, approved: AttoTokens) -> String {
+    format!("The price rose to {price} above the {approved} approved, nothing was paid")
+}
+
+/// Wallet paying for uploads
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletBalance {
+    /// Hex address of the wallet
+    pub address: String,
+    pub tokens: AttoTokens,
+    /// Gas balance in wei
+    pub gas: u128,
+}
+
+impl WalletBalance {
+    /// Token balance in ANT
+    pub fn ant(&self) -> f64 {
+        whole_tokens(&self.tokens)
+    }
+
+    /// Gas balance in ETH
+    pub fn eth(&self) -> f64 {
+        self.gas as f64 / 1e18
+    }
+
+    /// Whether the wallet can't pay the quote, tokens or gas
+    pub fn is_short_of(&self, quote: &Quote) -> bool {
+        self.tokens < quote.tokens || self.gas < quote.gas
+    }
+}
+
+fn whole_tokens(tokens: &AttoTokens) -> f64 {
+    let atto: f64 = tokens.as_atto().to_string().parse().unwrap_or(f64::MAX);
+    atto / 1e18
+}
+
+pub trait StorageBackend: Clone + Send + Sync + 'static {
+    /// Connect to the given environment and load the wallet from the secret key
+    fn init(
+        secret_key: &str,
+        environment: &str,
+    ) -> impl Future<Output = Result<Self, String>> + Send;
+
+    /// Upload public data, paying with the receipt if given or with the wallet otherwise
+    fn put(
+        &self,
+        data: Bytes,
+        receipt: Option<Receipt>,
+    ) -> impl Future<Output = Result<(AttoTokens, DataAddress), PutError>> + Send;
+
+    /// Get the cost of storing the chunks, chunks already stored on the network are free
+    fn quote_chunks(
+        &self,
+        chunks: &[(XorName, usize)],
+    ) -> impl Future<Output = Result<Quote, String>> + Send;
+
+    /// Pay for storing the chunks with the wallet, or reuse the receipt of a previous payment.
+    /// The chunks are quoted again before paying, a price above `approved` is refused before
+    /// anything is sent. Returns the receipt along with the amount paid.
+    fn pay(
+        &self,
+        chunks: &[(XorName, usize)],
+        receipt: Option<Receipt>,
+        approved: AttoTokens,
+    ) -> impl Future<Output = Result<(Receipt, AttoTokens), String>> + Send;
+
+    /// Store chunks paid for in the receipt
+    fn put_chunks(
+        &self,
+        chunks: Vec<Chunk>,
+        receipt: &Receipt,
+    ) -> impl Future<Output = Result<(), PutError>> + Send;
+
+    /// Address and balances of the wallet
+    fn balance(&self) -> impl Future<Output = Result<WalletBalance, String>> + Send;
+
+    /// Download public data
+    fn get(&self, address: &DataAddress) -> impl Future<Output = Result<Bytes, String>> + Send;
+
+    /// Download private data from its data map chunk
+    fn get_private(
+        &self,
+        data_map_chunk: &Chunk,
+    ) -> impl Future<Output = Result<Bytes, String>> + Send;
+
+    /// Get the cost of uploading the data
+    fn quote(&self, data: Bytes) -> impl Future<Output = Result<AttoTokens, String>> + Send;
+}
@@ -0,0 +1,241 @@
+use super::{PutError, Quote, StorageBackend, WalletBalance, price_rose};
+use crate::config::NetworkProfile;
+use crate::server::ENVIRONMENTS;
+
+use autonomi::client::DataMapChunk;
+use autonomi::client::payment::{PaymentOption, Receipt, receipt_from_store_quotes};
+use autonomi::data::DataAddress;
+use autonomi::{
+    AttoTokens, Bytes, Chunk, Client, ClientConfig, DataTypes, EvmNetwork, InitialPeersConfig,
+    Multiaddr, Wallet, XorName,
+};
+use tracing::{error, info};
+
+/// Funded key of the local testnet, used when no key is given for the "local" environment
+pub const DEFAULT_LOCAL_SECRET_KEY: &str =
+    "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+/// Rough gas used to pay for one chunk, payments are batched in a few transactions
+const ESTIMATED_GAS_PER_CHUNK: u128 = 30_000;
+/// Rough gas used by a payment transaction whatever the number of chunks it pays for
+const ESTIMATED_GAS_PER_TRANSACTION: u128 = 100_000;
+/// Rough gas price on Arbitrum One in wei, 0.01 gwei
+const ESTIMATED_GAS_PRICE: u128 = 10_000_000;
+
+/// The Autonomi Network, paying for uploads with an EVM wallet
+#[derive(Clone)]
+pub struct AutonomiBackend {
+    wallet: Wallet,
+    client: Client,
+}
+
+impl AutonomiBackend {
+    /// Connect to a user-defined network and load the wallet paying on its EVM network
+    pub async fn init_with_network(
+        secret_key: &str,
+        network: &NetworkProfile,
+    ) -> Result<Self, String> {
+        info!("Initializing client with network: {:?}", network.name);
+
+        let client = init_network_client(network).await?;
+        info!("Client initialized");
+        Self::with_wallet(client, secret_key)
+    }
+
+    fn with_wallet(client: Client, secret_key: &str) -> Result<Self, String> {
+        let evm_network = client.evm_network();
+        info!("EVM network: {evm_network:?}");
+
+        let wallet =
+            Wallet::new_from_private_key(evm_network.clone(), secret_key).map_err(|e| {
+                error!("Error loading wallet: {e}");
+                format!("Error loading wallet: {e}")
+            })?;
+        info!("Wallet loaded");
+
+        Ok(Self { wallet, client })
+    }
+}
+
+impl StorageBackend for AutonomiBackend {
+    async fn init(mut secret_key: &str, environment: &str) -> Result<Self, String> {
+        info!("Initializing client with environment: {environment:?}");
+
+        let client = init_client(environment).await?;
+        info!("Client initialized");
+
+        if environment == "local" && secret_key.is_empty() {
+            secret_key = DEFAULT_LOCAL_SECRET_KEY;
+        }
+        Self::with_wallet(client, secret_key)
+    }
+
+    async fn put(
+        &self,
+        data: Bytes,
+        receipt: Option<Receipt>,
+    ) -> Result<(AttoTokens, DataAddress), PutError> {
+        let payment = match receipt {
+            Some(receipt) => PaymentOption::Receipt(receipt),
+            None => PaymentOption::Wallet(self.wallet.clone()),
+        };
+
+        match self.client.data_put_public(data, payment).await {
+            Ok(res) => Ok(res),
+            Err(autonomi::client::PutError::Batch(upload_state)) => {
+                Err(PutError::Batch(upload_state))
+            }
+            Err(e) => Err(PutError::Other(e.to_string())),
+        }
+    }
+
+    async fn quote_chunks(&self, chunks: &[(XorName, usize)]) -> Result<Quote, String> {
+        let quote = self
+            .client
+            .get_store_quotes(DataTypes::Chunk, chunks.iter().copied())
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // only the chunks not yet stored on the network are quoted and paid for
+        let paid_chunks = quote.0.len();
+        let transaction_gas = if paid_chunks > 0 {
+            ESTIMATED_GAS_PER_TRANSACTION * ESTIMATED_GAS_PRICE
+        } else {
+            0
+        };
+        Ok(Quote {
+            tokens: AttoTokens::from_atto(quote.price()),
+            paid_chunks,
+            gas: transaction_gas
+                + paid_chunks as u128 * ESTIMATED_GAS_PER_CHUNK * ESTIMATED_GAS_PRICE,
+            transaction_gas,
+        })
+    }
+
+    async fn pay(
+        &self,
+        chunks: &[(XorName, usize)],
+        receipt: Option<Receipt>,
+        approved: AttoTokens,
+    ) -> Result<(Receipt, AttoTokens), String> {
+        if let Some(receipt) = receipt {
+            return Ok((receipt, AttoTokens::zero()));
+        }
+
+        // the quotes paid are the ones checked against the approved price
+        let quote = self
+            .client
+            .get_store_quotes(DataTypes::Chunk, chunks.iter().copied())
+            .await
+            .map_err(|e| e.to_string())?;
+        let price = AttoTokens::from_atto(quote.price());
+        if price > approved {
+            return Err(price_rose(price, approved));
+        }
+        self.wallet
+            .pay_for_quotes(quote.payments())
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok((receipt_from_store_quotes(quote), price))
+    }
+
+    async fn put_chunks(&self, chunks: Vec<Chunk>, receipt: &Receipt) -> Result<(), PutError> {
+        match self
+            .client
+            .chunk_batch_upload(chunks.iter().collect(), receipt)
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(autonomi::client::PutError::Batch(upload_state)) => {
+                Err(PutError::Batch(upload_state))
+            }
+            Err(e) => Err(PutError::Other(e.to_string())),
+        }
+    }
+
+    async fn balance(&self) -> Result<WalletBalance, String> {
+        let tokens = self
+            .wallet
+            .balance_of_tokens()
+            .await
+            .map_err(|e| format!("Error getting token balance: {e}"))?;
+        let gas = self
+            .wallet
+            .balance_of_gas_tokens()
+            .await
+            .map_err(|e| format!("Error getting gas balance: {e}"))?;
+        Ok(WalletBalance {
+            address: self.wallet.address().to_string(),
+            tokens: AttoTokens::from_atto(tokens),
+            gas: u128::try_from(gas).unwrap_or(u128::MAX),
+        })
+    }
+
+    async fn get(&self, address: &DataAddress) -> Result<Bytes, String> {
+        self.client
+            .data_get_public(address)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn get_private(&self, data_map_chunk: &Chunk) -> Result<Bytes, String> {
+        let data_map = DataMapChunk::from(data_map_chunk.clone());
+        self.client
+            .data_get(&data_map)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn quote(&self, data: Bytes) -> Result<AttoTokens, String> {
+        self.client.data_cost(data).await.map_err(|e| e.to_string())
+    }
+}
+
+async fn init_client(environment: &str) -> Result<Client, String> {
+    let res = match environment {
+        "local" => Client::init_local().await,
+        "alpha" => Client::init_alpha().await,
+        "autonomi" => Client::init().await,
+        // never fall back to the main network, where uploads cost real tokens
+        _ => {
+            return Err(format!(
+                "Unknown environment {environment:?}, expected one of {}",
+                ENVIRONMENTS.join(", ")
+            ));
+        }
+    };
+    res.map_err(|e| {
+        error!("Error initializing client: {e}");
+        format!("Error initializing client: {e}")
+    })
+}
+
+async fn init_network_client(network: &NetworkProfile) -> Result<Client, String> {
+    let addrs = network
+        .bootstrap_peers
+        .iter()
+        .map(|peer| {
+            peer.parse::<Multiaddr>()
+                .map_err(|e| format!("Invalid bootstrap peer {peer}: {e}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let config = ClientConfig {
+        init_peers_config: InitialPeersConfig {
+            addrs,
+            // peers cached from other networks would lead elsewhere
+            ignore_cache: true,
+            ..Default::default()
+        },
+        evm_network: EvmNetwork::new_custom(
+            &network.evm_rpc_url,
+            &network.payment_token_address,
+            &network.vault_address,
+        ),
+        network_id: network.network_id,
+        ..Default::default()
+    };
+    Client::init_with_config(config).await.map_err(|e| {
+        error!("Error initializing client: {e}");
+        format!("Error initializing client: {e}")
+    })
+}
@@ -0,0 +1,268 @@
+use super::{PutError, Quote, StorageBackend, WalletBalance, price_rose};
+
+use autonomi::client::ChunkBatchUploadState;
+use autonomi::client::payment::Receipt;
+use autonomi::data::DataAddress;
+use autonomi::{AttoTokens, Bytes, Chunk, ProofOfPayment, XorName};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Tokens in the fake wallet before any upload, 1 ANT
+const INITIAL_BALANCE: u64 = 1_000_000_000_000_000_000;
+/// Gas fees of a payment in wei, whatever the number of chunks it pays for
+const TRANSACTION_GAS: u128 = 21_000;
+
+/// In-memory fake of the network for running the app and its tests offline.
+/// Uploads cost one atto per byte and a fixed gas fee per payment, paid from a wallet starting with 1 ANT.
+/// Like on an EVM chain, a wallet payment fails if another one used its nonce while it was sent.
+/// Failures can be injected with [`MemoryBackend::fail_next_puts`], and prices changed with
+/// [`MemoryBackend::make_storage_free`] or [`MemoryBackend::raise_prices`].
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    records: Arc<Mutex<HashMap<DataAddress, Bytes>>>,
+    chunks: Arc<Mutex<HashMap<XorName, Bytes>>>,
+    failing_puts: Arc<AtomicUsize>,
+    free: Arc<AtomicBool>,
+    raised: Arc<AtomicBool>,
+    wallet_payments: Arc<AtomicUsize>,
+    receipt_payments: Arc<AtomicUsize>,
+    spent: Arc<AtomicU64>,
+    nonce: Arc<AtomicU64>,
+    chunk_writes: Arc<AtomicUsize>,
+}
+
+impl MemoryBackend {
+    /// Make the next `count` data or chunk batch puts fail with a batch error carrying a reusable payment.
+    /// A failing chunk batch still stores all its chunks but the first.
+    pub fn fail_next_puts(&self, count: usize) {
+        self.failing_puts.store(count, Ordering::SeqCst);
+    }
+
+    /// Make storing chunks cost no tokens, like a network whose nodes ask for none.
+    /// Chunks not stored yet still have to be paid for.
+    pub fn make_storage_free(&self) {
+        self.free.store(true, Ordering::SeqCst);
+    }
+
+    /// Double the price of storing chunks from now on, like a network getting busier
+    pub fn raise_prices(&self) {
+        self.raised.store(true, Ordering::SeqCst);
+    }
+
+    /// Tokens charged for storing the bytes
+    fn price(&self, size: usize) -> u64 {
+        if self.free.load(Ordering::SeqCst) {
+            0
+        } else if self.raised.load(Ordering::SeqCst) {
+            2 * size as u64
+        } else {
+            size as u64
+        }
+    }
+
+    /// Number of uploads paid for with the wallet
+    pub fn wallet_payments(&self) -> usize {
+        self.wallet_payments.load(Ordering::SeqCst)
+    }
+
+    /// Number of uploads paid for with a previously made payment
+    pub fn receipt_payments(&self) -> usize {
+        self.receipt_payments.load(Ordering::SeqCst)
+    }
+
+    /// Number of chunks written, counting the ones written again
+    pub fn chunk_writes(&self) -> usize {
+        self.chunk_writes.load(Ordering::SeqCst)
+    }
+
+    /// Number of chunks stored
+    pub fn stored_chunks(&self) -> usize {
+        self.chunks
+            .lock()
+            .map(|chunks| chunks.len())
+            .unwrap_or_default()
+    }
+
+    /// Send a wallet transaction with the next nonce, failing if another took it in the meantime
+    async fn send_transaction(&self) -> Result<(), String> {
+        let nonce = self.nonce.load(Ordering::SeqCst);
+        tokio::task::yield_now().await;
+        self.nonce
+            .compare_exchange(nonce, nonce + 1, Ordering::SeqCst, Ordering::SeqCst)
+            .map(|_| ())
+            .map_err(|_| format!("Transaction failed: nonce {nonce} already used"))
+    }
+
+    fn take_failure(&self) -> bool {
+        self.failing_puts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    async fn init(_secret_key: &str, _environment: &str) -> Result<Self, String> {
+        Ok(Self::default())
+    }
+
+    async fn put(
+        &self,
+        data: Bytes,
+        receipt: Option<Receipt>,
+    ) -> Result<(AttoTokens, DataAddress), PutError> {
+        let cost = match receipt {
+            Some(_) => {
+                self.receipt_payments.fetch_add(1, Ordering::SeqCst);
+                AttoTokens::zero()
+            }
+            None => {
+                self.send_transaction().await.map_err(PutError::Other)?;
+                self.wallet_payments.fetch_add(1, Ordering::SeqCst);
+                let price = self.price(data.len());
+                self.spent.fetch_add(price, Ordering::SeqCst);
+                AttoTokens::from_u64(price)
+            }
+        };
+
+        if self.take_failure() {
+            let (data_map_chunk, chunks) = autonomi::self_encryption::encrypt(data.clone())
+                .map_err(|e| PutError::Other(e.to_string()))?;
+            let chunks: Vec<_> = chunks
+                .iter()
+                .chain([&data_map_chunk])
+                .map(|chunk| (*chunk.name(), chunk.size()))
+                .collect();
+            return Err(PutError::Batch(ChunkBatchUploadState {
+                payment: Some(receipt_for(&chunks)),
+                ..Default::default()
+            }));
+        }
+
+        let address = DataAddress::new(XorName::from_content(&data));
+        let mut records = self
+            .records
+            .lock()
+            .map_err(|e| PutError::Other(e.to_string()))?;
+        records.insert(address, data);
+        Ok((cost, address))
+    }
+
+    async fn quote_chunks(&self, chunks: &[(XorName, usize)]) -> Result<Quote, String> {
+        // like on the network, chunks already stored cost nothing
+        let stored = self.chunks.lock().map_err(|e| e.to_string())?;
+        let unstored: Vec<_> = chunks
+            .iter()
+            .filter(|(name, _)| !stored.contains_key(name))
+            .collect();
+        let size: usize = unstored.iter().map(|(_, size)| size).sum();
+        let gas = if unstored.is_empty() {
+            0
+        } else {
+            TRANSACTION_GAS
+        };
+        Ok(Quote {
+            tokens: AttoTokens::from_u64(self.price(size)),
+            paid_chunks: unstored.len(),
+            gas,
+            transaction_gas: gas,
+        })
+    }
+
+    async fn pay(
+        &self,
+        chunks: &[(XorName, usize)],
+        receipt: Option<Receipt>,
+        approved: AttoTokens,
+    ) -> Result<(Receipt, AttoTokens), String> {
+        if let Some(receipt) = receipt {
+            self.receipt_payments.fetch_add(1, Ordering::SeqCst);
+            return Ok((receipt, AttoTokens::zero()));
+        }
+
+        let size: usize = chunks.iter().map(|(_, size)| size).sum();
+        let price = self.price(size);
+        if AttoTokens::from_u64(price) > approved {
+            return Err(price_rose(AttoTokens::from_u64(price), approved));
+        }
+        self.send_transaction().await?;
+        self.wallet_payments.fetch_add(1, Ordering::SeqCst);
+        self.spent.fetch_add(price, Ordering::SeqCst);
+        Ok((receipt_for(chunks), AttoTokens::from_u64(price)))
+    }
+
+    async fn put_chunks(&self, chunks: Vec<Chunk>, receipt: &Receipt) -> Result<(), PutError> {
+        // a failing batch stores all its chunks but the first
+        let failing = self.take_failure();
+        let mut state = ChunkBatchUploadState {
+            payment: Some(receipt.clone()),
+            ..Default::default()
+        };
+
+        let mut stored = self
+            .chunks
+            .lock()
+            .map_err(|e| PutError::Other(e.to_string()))?;
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            if failing && i == 0 {
+                let error = "injected failure".to_string();
+                state.failed.push((*chunk.address(), error));
+                continue;
+            }
+            self.chunk_writes.fetch_add(1, Ordering::SeqCst);
+            state.successful.push(*chunk.address());
+            stored.insert(*chunk.name(), chunk.value().clone());
+        }
+
+        if failing {
+            return Err(PutError::Batch(state));
+        }
+        Ok(())
+    }
+
+    async fn balance(&self) -> Result<WalletBalance, String> {
+        let spent = self.spent.load(Ordering::SeqCst);
+        Ok(WalletBalance {
+            address: format!("0x{}", "0".repeat(40)),
+            tokens: AttoTokens::from_u64(INITIAL_BALANCE.saturating_sub(spent)),
+            gas: INITIAL_BALANCE as u128,
+        })
+    }
+
+    async fn get(&self, address: &DataAddress) -> Result<Bytes, String> {
+        let records = self.records.lock().map_err(|e| e.to_string())?;
+        if let Some(data) = records.get(address) {
+            return Ok(data.clone());
+        }
+
+        // data uploaded as chunks is decrypted from its data map chunk
+        let chunks = self.chunks.lock().map_err(|e| e.to_string())?;
+        let data_map = chunks
+            .get(address.xorname())
+            .ok_or_else(|| format!("No data found at {}", address.to_hex()))?;
+        let data_map_chunk = Chunk::new(data_map.clone());
+        crate::encryption::decrypt(&data_map_chunk, |name| chunks.get(name).cloned())
+    }
+
+    async fn get_private(&self, data_map_chunk: &Chunk) -> Result<Bytes, String> {
+        let chunks = self.chunks.lock().map_err(|e| e.to_string())?;
+        crate::encryption::decrypt(data_map_chunk, |name| chunks.get(name).cloned())
+    }
+
+    async fn quote(&self, data: Bytes) -> Result<AttoTokens, String> {
+        Ok(AttoTokens::from_u64(data.len() as u64))
+    }
+}
+
+/// Receipt with an entry per chunk paid for, at one atto per byte
+fn receipt_for(chunks: &[(XorName, usize)]) -> Receipt {
+    chunks
+        .iter()
+        .map(|(name, size)| {
+            let proof = ProofOfPayment {
+                peer_quotes: Vec::new(),
+            };
+            (*name, (proof, AttoTokens::from_u64(*size as u64)))
+        })
+        .collect()
+}
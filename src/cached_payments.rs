@@ -9,14 +9,43 @@
 use autonomi::client::{ChunkBatchUploadState, payment::Receipt};
 use color_eyre::Section;
 use color_eyre::eyre::{Context, Result, eyre};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::{DirEntry, File};
 use std::io::{BufReader, BufWriter};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
 
-// Cleanup old cached payments after 30 days
-const PAYMENT_EXPIRATION_SECS: u64 = 3600 * 24 * 30;
+/// Cleanup old cached payments after 30 days unless configured otherwise
+pub const DEFAULT_PAYMENT_EXPIRATION: Duration = Duration::from_secs(3600 * 24 * 30);
+
+static PAYMENT_EXPIRATION_SECS: AtomicU64 = AtomicU64::new(DEFAULT_PAYMENT_EXPIRATION.as_secs());
+
+/// Set how long cached payments are kept before being cleaned up, never less than
+/// [`DEFAULT_PAYMENT_EXPIRATION`] so payments cached by the ant CLI outlive its own expiry
+pub fn set_payment_expiration(expiration: Duration) {
+    let expiration = expiration.max(DEFAULT_PAYMENT_EXPIRATION);
+    PAYMENT_EXPIRATION_SECS.store(expiration.as_secs(), Ordering::SeqCst);
+}
+
+fn payment_expiration_secs() -> u64 {
+    PAYMENT_EXPIRATION_SECS.load(Ordering::SeqCst)
+}
+
+static CLIENT_DATA_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Keep the cached payments, upload history and config in the dir instead of the autonomi
+/// client data dir, e.g. to keep tests off the user's payments
+pub fn set_client_data_dir(dir: PathBuf) {
+    *CLIENT_DATA_DIR.write().unwrap_or_else(|e| e.into_inner()) = Some(dir);
+}
+
+/// Held while the payment sources file is read and written back, so concurrent uploads don't
+/// drop each other's sources
+static PAYMENT_SOURCES_LOCK: Mutex<()> = Mutex::new(());
 
 pub fn get_payments_dir() -> Result<PathBuf> {
     let dir = get_client_data_dir_path()?;
@@ -28,57 +57,296 @@ pub fn get_payments_dir() -> Result<PathBuf> {
 
 /// Save the payment for the given file name to be reused later.
 pub fn save_payment(file: &str, upload_state: &ChunkBatchUploadState) -> Result<()> {
+    save_payment_with_key(file, &filename_short(file), upload_state)
+}
+
+/// Save the payment for the given content hash to be reused later by any file with this content.
+/// The source is remembered so the upload can be retried from the cached payments list in the
+/// mode the payment was made for.
+pub fn save_payment_for_content(
+    content_hash: &str,
+    source: Option<&UploadSource>,
+    upload_state: &ChunkBatchUploadState,
+) -> Result<()> {
+    if let Some(source) = source {
+        let _lock = PAYMENT_SOURCES_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let mut sources = read_payment_sources()?;
+        sources.insert(
+            content_hash.to_string(),
+            PaymentSource::File(source.clone()),
+        );
+        let file = File::create(payment_sources_path()?)?;
+        serde_json::to_writer(BufWriter::new(&file), &sources)?;
+    }
+    save_payment_with_key(content_hash, content_hash, upload_state)
+}
+
+fn save_payment_with_key(
+    label: &str,
+    key: &str,
+    upload_state: &ChunkBatchUploadState,
+) -> Result<()> {
     let dir = get_payments_dir()?;
     let timestamp =
         get_timestamp_from_receipt(upload_state.payment.as_ref().unwrap_or(&HashMap::new()));
-    let file_hash = filename_short(file);
-    let file_path = dir.join(format!("{timestamp}_{file_hash}"));
+    let file_path = dir.join(format!("{timestamp}_{key}"));
 
     let file = File::create(&file_path)?;
     let writer = BufWriter::new(&file);
     serde_json::to_writer(writer, &upload_state)?;
 
-    println!("Cached payment for {file:?} to {}", file_path.display());
+    info!("Cached payment for {label:?} to {}", file_path.display());
     Ok(())
 }
 
 /// Load the payment for the given file name.
 /// Returns None if no payment is found.
 pub fn load_payment_for_file(file_name: &str) -> Result<Option<Receipt>> {
+    load_payment_with_key(file_name, &filename_short(file_name))
+}
+
+/// Load the payment for the given content hash, falling back to a payment cached
+/// under the file name by older versions or the ant CLI.
+/// Returns None if no payment is found.
+pub fn load_payment_for_content(content_hash: &str, file_name: &str) -> Result<Option<Receipt>> {
+    match load_payment_with_key(file_name, content_hash)? {
+        Some(receipt) => Ok(Some(receipt)),
+        None => load_payment_for_file(file_name),
+    }
+}
+
+fn load_payment_with_key(label: &str, key: &str) -> Result<Option<Receipt>> {
     cleanup_outdated_payments()?;
 
     let dir = get_payments_dir()?;
-    let file_hash = filename_short(file_name);
-
     let files = std::fs::read_dir(dir)?;
     for file in files {
-        if let Some(path) = matches_filename(file.ok(), &file_hash) {
-            let file = File::open(path)?;
-            let reader = BufReader::new(file);
-            let receipt: Receipt = serde_json::from_reader(reader)?;
-            println!("Found cached payment for {file_name}");
-            return Ok(Some(receipt));
+        if let Some(path) = matches_filename(file.ok(), key) {
+            let receipt = read_receipt(&path)?;
+            info!("Found cached payment for {label}");
+            return Ok(receipt);
         }
     }
 
     Ok(None)
 }
 
+/// A payment cached on disk after a failed upload
+#[derive(Debug, Clone)]
+pub struct CachedPayment {
+    /// Path of the cached payment file
+    pub path: PathBuf,
+    /// Content hash, or file name for payments cached by the ant CLI
+    pub key: String,
+    /// File the payment was made for, if known. For data encrypted with a passphrase,
+    /// the encrypted copy that was uploaded.
+    pub source: Option<PathBuf>,
+    /// Whether the payment was made for the file with its data map kept private
+    pub private: bool,
+    /// Name of the file the upload was started for, if other than the name of the source
+    pub filename: Option<String>,
+    /// Whether the source is a copy encrypted with a passphrase, kept until the payment is removed
+    pub passphrase: bool,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    /// Number of chunks paid for
+    pub chunks: usize,
+}
+
+impl CachedPayment {
+    pub fn age(&self) -> Duration {
+        UNIX_EPOCH
+            .checked_add(Duration::from_secs(self.timestamp))
+            .and_then(|paid_at| SystemTime::now().duration_since(paid_at).ok())
+            .unwrap_or_default()
+    }
+
+    /// Time left before the payment is cleaned up as expired
+    pub fn expires_in(&self) -> Duration {
+        Duration::from_secs(payment_expiration_secs()).saturating_sub(self.age())
+    }
+}
+
+/// List the cached payments, oldest first
+pub fn list_cached_payments() -> Result<Vec<CachedPayment>> {
+    cleanup_outdated_payments()?;
+
+    let sources = read_payment_sources()?;
+    let mut payments = Vec::new();
+    for file in std::fs::read_dir(get_payments_dir()?)? {
+        let path = file?.path();
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        let Some((timestamp, key)) = file_name.split_once('_') else {
+            continue;
+        };
+        let chunks = match read_receipt(&path) {
+            Ok(receipt) => receipt.map(|r| r.len()).unwrap_or_default(),
+            Err(e) => {
+                warn!("Skipping unreadable cached payment {}: {e}", path.display());
+                continue;
+            }
+        };
+        let source = sources.get(key);
+        let upload = match source {
+            Some(PaymentSource::File(upload)) => Some(upload),
+            _ => None,
+        };
+        payments.push(CachedPayment {
+            source: source.map(|source| source.path().to_path_buf()),
+            private: upload.is_some_and(|upload| upload.private),
+            filename: upload.and_then(|upload| upload.filename.clone()),
+            passphrase: upload.is_some_and(|upload| upload.passphrase),
+            key: key.to_string(),
+            timestamp: timestamp.parse().unwrap_or_default(),
+            chunks,
+            path,
+        });
+    }
+    payments.sort_by_key(|payment| payment.timestamp);
+    Ok(payments)
+}
+
+/// Delete a cached payment, the tokens it paid can't be reused afterwards
+pub fn delete_cached_payment(payment: &CachedPayment) -> Result<()> {
+    std::fs::remove_file(&payment.path)
+        .wrap_err_with(|| format!("Could not delete {}", payment.path.display()))?;
+    remove_payment_source(&payment.key)
+}
+
+/// Forget the source of the payments cached under the key, removing the encrypted copy
+/// kept for a passphrase upload
+fn remove_payment_source(key: &str) -> Result<()> {
+    let _lock = PAYMENT_SOURCES_LOCK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let mut sources = read_payment_sources()?;
+    let Some(source) = sources.remove(key) else {
+        return Ok(());
+    };
+    let file = File::create(payment_sources_path()?)?;
+    serde_json::to_writer(BufWriter::new(&file), &sources)?;
+    if let PaymentSource::File(UploadSource {
+        path,
+        passphrase: true,
+        ..
+    }) = source
+    {
+        crate::passphrase::remove_sealed(&path);
+    }
+    Ok(())
+}
+
+/// Whether a cached payment was made for the file, which is then kept for its retry
+pub fn is_payment_source(path: &Path) -> bool {
+    let _lock = PAYMENT_SOURCES_LOCK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    read_payment_sources().is_ok_and(|sources| sources.values().any(|source| source.path() == path))
+}
+
+/// Delete the payments cached for the content hash, once its upload no longer needs them
+pub fn remove_payment_for_content(content_hash: &str) -> Result<()> {
+    let payments = list_cached_payments()?;
+    for payment in payments
+        .iter()
+        .filter(|payment| payment.key == content_hash)
+    {
+        delete_cached_payment(payment)?;
+    }
+    Ok(())
+}
+
+/// Copy a cached payment file into the directory, returns the path of the copy
+pub fn export_cached_payment(payment: &CachedPayment, dir: &Path) -> Result<PathBuf> {
+    let file_name = payment.path.file_name().unwrap_or_default();
+    let destination = dir.join(format!("{}.json", file_name.to_string_lossy()));
+    std::fs::copy(&payment.path, &destination)
+        .wrap_err_with(|| format!("Could not export to {}", destination.display()))?;
+    Ok(destination)
+}
+
+/// File a payment was made for, along with how it was uploaded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadSource {
+    /// File uploaded, for a passphrase upload the encrypted copy
+    pub path: PathBuf,
+    /// Whether the data map is kept private
+    pub private: bool,
+    /// Name of the file the upload was started for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// Whether the file was encrypted with a passphrase
+    #[serde(default)]
+    pub passphrase: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum PaymentSource {
+    /// Public file, as saved by older versions
+    Path(PathBuf),
+    File(UploadSource),
+}
+
+impl PaymentSource {
+    fn path(&self) -> &Path {
+        match self {
+            PaymentSource::Path(path) | PaymentSource::File(UploadSource { path, .. }) => path,
+        }
+    }
+}
+
+/// The sources are kept out of the payments dir where every file is expected to be a payment
+fn payment_sources_path() -> Result<PathBuf> {
+    Ok(get_client_data_dir_path()?.join("ant_upload_payment_sources.json"))
+}
+
+fn read_payment_sources() -> Result<HashMap<String, PaymentSource>> {
+    let path = payment_sources_path()?;
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Read the receipt from a cached payment file, saved either as the upload state or as a receipt
+fn read_receipt(path: &Path) -> Result<Option<Receipt>> {
+    let reader = BufReader::new(File::open(path)?);
+    if let Ok(upload_state) = serde_json::from_reader::<_, ChunkBatchUploadState>(reader) {
+        return Ok(upload_state.payment);
+    }
+
+    let reader = BufReader::new(File::open(path)?);
+    let receipt: Receipt = serde_json::from_reader(reader)?;
+    Ok(Some(receipt))
+}
+
 /// Cleanup outdated cached payments.
 fn cleanup_outdated_payments() -> Result<()> {
     let dir = get_payments_dir()?;
     let files = std::fs::read_dir(dir)?;
     let expired_files = files.into_iter().filter_map(|file| {
         let path = file.ok()?.path();
-        if is_expired_file(path.to_str()?) {
+        if is_expired_file(path.file_name()?.to_str()?) {
             Some(path)
         } else {
             None
         }
     });
     for file in expired_files {
-        println!("Removing expired cached payment file: {}", file.display());
-        std::fs::remove_file(file)?;
+        info!("Removing expired cached payment file: {}", file.display());
+        std::fs::remove_file(&file)?;
+        let key = file
+            .file_name()
+            .and_then(|name| name.to_str()?.split_once('_'));
+        if let Some((_, key)) = key {
+            remove_payment_source(key)?;
+        }
     }
     Ok(())
 }
@@ -89,14 +357,20 @@ fn matches_filename(file: Option<DirEntry>, file_hash: &str) -> Option<PathBuf>
         return None;
     }
     let file_name = path.file_name()?;
-    let file_name = file_name.to_str()?;
-    if file_name.contains(file_hash) {
+    if is_payment_for(file_name.to_str()?, file_hash) {
         Some(path)
     } else {
         None
     }
 }
 
+/// Whether the cached payment file, named `{timestamp}_{key}`, is the one of the key
+fn is_payment_for(file_name: &str, key: &str) -> bool {
+    file_name
+        .split_once('_')
+        .is_some_and(|(_, file_key)| file_key == key)
+}
+
 /// if filename is longer than 32 characters or is a path, use the hash instead
 fn filename_short(filename: &str) -> String {
     if filename.len() > 32 || filename.contains("/") || filename.contains("\\") {
@@ -107,13 +381,14 @@ fn filename_short(filename: &str) -> String {
 }
 
 fn is_expired_file(filename: &str) -> bool {
-    let exp = PAYMENT_EXPIRATION_SECS;
-    let expired_if_before = SystemTime::now() - Duration::from_secs(exp);
-
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    // as durations since the epoch, so no expiration or timestamp can overflow the clock
     let timestr = filename.split('_').next().unwrap_or_default();
     let sec = timestr.parse::<u64>().unwrap_or_default();
-    let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(sec);
-    timestamp < expired_if_before
+    let expiration = Duration::from_secs(payment_expiration_secs());
+    Duration::from_secs(sec).saturating_add(expiration) < now
 }
 
 fn now() -> String {
@@ -143,10 +418,17 @@ fn get_timestamp_from_receipt(receipt: &Receipt) -> String {
 }
 
 pub fn get_client_data_dir_path() -> Result<PathBuf> {
-    let mut home_dirs = dirs_next::data_dir()
-        .ok_or_else(|| eyre!("Failed to obtain data dir, your OS might not be supported."))?;
-    home_dirs.push("autonomi");
-    home_dirs.push("client");
+    let data_dir = CLIENT_DATA_DIR
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let home_dirs = match data_dir {
+        Some(dir) => dir,
+        None => dirs_next::data_dir()
+            .ok_or_else(|| eyre!("Failed to obtain data dir, your OS might not be supported."))?
+            .join("autonomi")
+            .join("client"),
+    };
     std::fs::create_dir_all(home_dirs.as_path())
         .wrap_err("Failed to create data dir")
         .with_suggestion(|| {
@@ -163,18 +445,19 @@ mod tests {
 
     #[test]
     fn test_is_expired_filename() {
-        let just_expired = (SystemTime::now() - Duration::from_secs(PAYMENT_EXPIRATION_SECS))
+        let expiration = payment_expiration_secs();
+        let just_expired = (SystemTime::now() - Duration::from_secs(expiration))
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs()
             .to_string();
-        let just_expired_1 = (SystemTime::now() - Duration::from_secs(PAYMENT_EXPIRATION_SECS + 1))
+        let just_expired_1 = (SystemTime::now() - Duration::from_secs(expiration + 1))
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs()
             .to_string();
         let not_expired = now();
-        let not_expired_1 = (SystemTime::now() + Duration::from_secs(PAYMENT_EXPIRATION_SECS - 1))
+        let not_expired_1 = (SystemTime::now() + Duration::from_secs(expiration - 1))
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs()
@@ -185,5 +468,15 @@ mod tests {
         assert!(is_expired_file(&format!("{just_expired_1}_{file_hash}")));
         assert!(!is_expired_file(&format!("{not_expired}_{file_hash}")));
         assert!(!is_expired_file(&format!("{not_expired_1}_{file_hash}")));
+        assert!(!is_expired_file(&format!("{}_{file_hash}", u64::MAX)));
+    }
+
+    #[test]
+    fn test_is_payment_for() {
+        assert!(is_payment_for("1700000000_report.pdf", "report.pdf"));
+        assert!(is_payment_for("1700000000_my_report.pdf", "my_report.pdf"));
+        assert!(!is_payment_for("1700000000_report.pdf", "report"));
+        assert!(!is_payment_for("1700000000_old_report.pdf", "report.pdf"));
+        assert!(!is_payment_for("report.pdf", "report.pdf"));
     }
 }
@@ -0,0 +1,171 @@
+use ant_upload::Server;
+use ant_upload::config::Config;
+use ant_upload::secret_key::SecretKey;
+use ant_upload::server::RetryPolicy;
+
+use clap::{Parser, Subcommand};
+use std::io::{BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+
+/// Environment variable the secret key is read from in headless mode
+const SECRET_KEY_ENV: &str = "SECRET_KEY";
+
+#[derive(Parser)]
+#[command(
+    name = "ant_upload",
+    version,
+    about = "Share your files with the world"
+)]
+pub struct Args {
+    /// Run headless instead of opening the window
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Upload files to the network without opening the window
+    Put {
+        /// Files to upload
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Network environment to upload to: local, autonomi, alpha or a configured network,
+        /// the configured environment if unset
+        #[arg(long)]
+        env: Option<String>,
+        /// Print one JSON object per file instead of plain lines
+        #[arg(long)]
+        json: bool,
+        /// Number of times chunks failing to upload are retried before giving up,
+        /// the configured number if unset
+        #[arg(long)]
+        retries: Option<u32>,
+        /// Keep the data maps private, printing them instead of addresses
+        #[arg(long)]
+        private: bool,
+    },
+}
+
+/// Run the given headless command with the settings of the config, returns the process exit code
+pub async fn run(command: Command, config: &Config) -> i32 {
+    match command {
+        Command::Put {
+            files,
+            env,
+            json,
+            retries,
+            private,
+        } => {
+            let env = env.unwrap_or_else(|| config.environment.clone());
+            // checked before asking for the secret key
+            let environments = config.environments();
+            if !environments.contains(&env.as_str()) {
+                eprintln!(
+                    "Unknown environment {env:?}, expected one of {}",
+                    environments.join(", ")
+                );
+                return 1;
+            }
+            let mut retry_policy = config.retry_policy();
+            retry_policy.retries = retries.unwrap_or(retry_policy.retries);
+            put(files, &env, json, retry_policy, private, config).await
+        }
+    }
+}
+
+async fn put(
+    files: Vec<PathBuf>,
+    env: &str,
+    json: bool,
+    retry_policy: RetryPolicy,
+    private: bool,
+    config: &Config,
+) -> i32 {
+    let secret_key = match read_secret_key() {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Error reading secret key: {e}");
+            return 1;
+        }
+    };
+
+    let server = match Server::connect(secret_key, env, config).await {
+        Ok(server) => server.with_retry_policy(retry_policy),
+        Err(e) => {
+            eprintln!("{e}");
+            return 1;
+        }
+    };
+
+    // Upload files one after the other so payments never race on the wallet
+    let mut failures = 0;
+    for path in files {
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+
+        let result = upload(&server, &path, &filename, private, config.max_upload_cost).await;
+
+        if result.is_err() {
+            failures += 1;
+        }
+        print_result(&path, &result, json);
+    }
+
+    if failures > 0 { 1 } else { 0 }
+}
+
+/// Quote the file then pay for and upload it, unless it costs more than the max upload cost
+async fn upload(
+    server: &Server,
+    path: &Path,
+    filename: &str,
+    private: bool,
+    max_upload_cost: Option<f64>,
+) -> Result<(String, String), String> {
+    let prepared = if private {
+        server.prepare_private_file(path, filename, &|_| {}).await?
+    } else {
+        server.prepare_file(path, filename, &|_| {}).await?
+    };
+    let cost = prepared.quote().ant();
+    if let Some(max) = max_upload_cost.filter(|max| cost > *max) {
+        return Err(format!(
+            "Refused to pay {cost} ANT, above the max_upload_cost of {max} ANT"
+        ));
+    }
+    server.upload_prepared(prepared, &|_| {}).await
+}
+
+fn print_result(path: &Path, result: &Result<(String, String), String>, json: bool) {
+    let file = path.display().to_string();
+    match (result, json) {
+        (Ok((address, cost)), true) => {
+            let line = serde_json::json!({ "file": file, "address": address, "cost": cost });
+            println!("{line}");
+        }
+        (Err(error), true) => {
+            let line = serde_json::json!({ "file": file, "error": error });
+            println!("{line}");
+        }
+        (Ok((address, cost)), false) => println!("{address} {cost} {file}"),
+        (Err(error), false) => eprintln!("Failed to upload {file}: {error}"),
+    }
+}
+
+/// Read the secret key from the SECRET_KEY env var, or from the first line of stdin
+fn read_secret_key() -> std::io::Result<SecretKey> {
+    if let Ok(mut key) = std::env::var(SECRET_KEY_ENV) {
+        return Ok(SecretKey::take_from(&mut key));
+    }
+
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprintln!("Enter SECRET_KEY (or set the {SECRET_KEY_ENV} env var):");
+    }
+    let mut key = String::new();
+    stdin.lock().read_line(&mut key)?;
+    Ok(SecretKey::take_from(&mut key))
+}
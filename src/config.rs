@@ -0,0 +1,286 @@
+//! Settings of the app saved as TOML in the client data dir, next to the upload history.
+
+use crate::server::{ENVIRONMENTS, MAX_RETRY_DELAY, RetryPolicy};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const CONFIG_FILE: &str = "ant_upload.toml";
+
+/// Payments are never cleaned up before the ant CLI would, it caches its payments in the same dir
+const MIN_PAYMENT_EXPIRY_DAYS: u64 =
+    crate::cached_payments::DEFAULT_PAYMENT_EXPIRATION.as_secs() / 86400;
+
+/// How much the app and the network client log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    #[default]
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 6] = [
+        LogLevel::Off,
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+/// User-defined network, such as a private testnet, selectable like the built-in environments
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkProfile {
+    /// Name shown in the environment selector
+    pub name: String,
+    /// Multiaddrs of the peers to join the network through
+    pub bootstrap_peers: Vec<String>,
+    /// URL of the RPC of the EVM network payments are made on
+    pub evm_rpc_url: String,
+    /// Address of the contract of the token uploads are paid with
+    pub payment_token_address: String,
+    /// Address of the contract payments are made to
+    pub vault_address: String,
+    /// Network id telling the network apart from others, if it has one
+    #[serde(default)]
+    pub network_id: Option<u8>,
+}
+
+impl NetworkProfile {
+    /// Check the profile can be used to connect, describing the first setting that can't
+    pub fn validate(&self) -> Result<(), String> {
+        let name = &self.name;
+        if name.trim().is_empty() {
+            return Err("network name can't be empty".to_string());
+        }
+        if ENVIRONMENTS.contains(&name.as_str()) {
+            return Err(format!(
+                "network {name:?} has the name of a built-in environment"
+            ));
+        }
+        if self.bootstrap_peers.is_empty() {
+            return Err(format!(
+                "network {name:?} needs at least one bootstrap peer"
+            ));
+        }
+        if let Some(peer) = self.bootstrap_peers.iter().find(|p| !p.starts_with('/')) {
+            return Err(format!(
+                "network {name:?} has an invalid bootstrap peer {peer:?}, expected a multiaddr like /ip4/1.2.3.4/udp/5000/quic-v1/p2p/<peer id>"
+            ));
+        }
+        if !self.evm_rpc_url.starts_with("http://") && !self.evm_rpc_url.starts_with("https://") {
+            return Err(format!(
+                "network {name:?} has an invalid evm_rpc_url {:?}, expected an http(s) URL",
+                self.evm_rpc_url
+            ));
+        }
+        let contracts = [
+            ("payment_token_address", &self.payment_token_address),
+            ("vault_address", &self.vault_address),
+        ];
+        for (setting, address) in contracts {
+            if !is_evm_address(address) {
+                return Err(format!(
+                    "network {name:?} has an invalid {setting} {address:?}, expected 0x followed by 40 hex digits"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_evm_address(address: &str) -> bool {
+    address
+        .strip_prefix("0x")
+        .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Settings of the app, any setting missing from the file takes its default value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Environment selected when the app starts: local, autonomi, alpha or the name of a network
+    pub environment: String,
+    /// Number of uploads running at once
+    pub max_concurrent: usize,
+    /// Number of times chunks failing to upload are retried before giving up
+    pub retries: u32,
+    /// Seconds before the first retry, doubled after every retry up to 10 minutes
+    pub retry_delay_secs: u64,
+    /// Uploads costing less than this many ANT are paid for without asking
+    pub auto_approve_below: Option<f64>,
+    /// Uploads costing more than this many ANT are refused without asking
+    pub max_upload_cost: Option<f64>,
+    /// Days payments of failed uploads are kept for retries, at least the 30 days the ant CLI
+    /// keeps the payments it shares the cache with
+    pub payment_expiry_days: u64,
+    pub log_level: LogLevel,
+    /// Networks selectable along with the built-in environments
+    pub networks: Vec<NetworkProfile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            environment: crate::DEFAULT_ENVIRONMENT.to_string(),
+            max_concurrent: crate::uploads::DEFAULT_MAX_CONCURRENT,
+            retries: RetryPolicy::default().retries,
+            retry_delay_secs: RetryPolicy::default().initial_delay.as_secs(),
+            auto_approve_below: None,
+            max_upload_cost: None,
+            payment_expiry_days: crate::cached_payments::DEFAULT_PAYMENT_EXPIRATION.as_secs()
+                / 86400,
+            log_level: LogLevel::default(),
+            networks: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Path of the config file in the client data dir
+    pub fn path() -> Result<PathBuf, String> {
+        let dir = crate::cached_payments::get_client_data_dir_path()
+            .map_err(|e| format!("Error getting data dir: {e}"))?;
+        Ok(dir.join(CONFIG_FILE))
+    }
+
+    /// Load the config from the client data dir
+    pub fn load() -> Result<Self, String> {
+        Self::load_from(&Self::path()?)
+    }
+
+    /// Load the config from the file, the default config if it doesn't exist yet
+    pub fn load_from(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                Self::parse(&text).map_err(|e| format!("Invalid config {}: {e}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Error reading config {}: {e}", path.display())),
+        }
+    }
+
+    /// Read the config from TOML and check its values
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the values are usable, describing the first one that is not
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, network) in self.networks.iter().enumerate() {
+            network.validate()?;
+            if self.networks[..i].iter().any(|n| n.name == network.name) {
+                return Err(format!("network {:?} is defined twice", network.name));
+            }
+        }
+        let environments = self.environments();
+        if !environments.contains(&self.environment.as_str()) {
+            return Err(format!(
+                "environment must be one of {}, not {:?}",
+                environments.join(", "),
+                self.environment
+            ));
+        }
+        if !(1..=32).contains(&self.max_concurrent) {
+            return Err(format!(
+                "max_concurrent must be between 1 and 32, not {}",
+                self.max_concurrent
+            ));
+        }
+        if self.retries > 10 {
+            return Err(format!("retries must be at most 10, not {}", self.retries));
+        }
+        let max_delay = MAX_RETRY_DELAY.as_secs();
+        if self.retry_delay_secs > max_delay {
+            return Err(format!(
+                "retry_delay_secs must be at most {max_delay}, not {}",
+                self.retry_delay_secs
+            ));
+        }
+        if !(MIN_PAYMENT_EXPIRY_DAYS..=365).contains(&self.payment_expiry_days) {
+            return Err(format!(
+                "payment_expiry_days must be between {MIN_PAYMENT_EXPIRY_DAYS} and 365, not {}",
+                self.payment_expiry_days
+            ));
+        }
+        let limits = [
+            ("auto_approve_below", self.auto_approve_below),
+            ("max_upload_cost", self.max_upload_cost),
+        ];
+        for (name, limit) in limits {
+            if limit.is_some_and(|ant| !ant.is_finite() || ant < 0.0) {
+                return Err(format!("{name} must be zero or more ANT"));
+            }
+        }
+        if let (Some(auto), Some(max)) = (self.auto_approve_below, self.max_upload_cost) {
+            if auto > max {
+                return Err(format!(
+                    "auto_approve_below ({auto} ANT) can't be above max_upload_cost ({max} ANT)"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Save the config to the client data dir
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let path = Self::path()?;
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    /// Save the config to the file, refusing invalid values
+    pub fn save_to(&self, path: &Path) -> Result<(), String> {
+        self.validate()
+            .map_err(|e| format!("Invalid config: {e}"))?;
+        let text =
+            toml::to_string_pretty(self).map_err(|e| format!("Error serializing config: {e}"))?;
+        std::fs::write(path, text)
+            .map_err(|e| format!("Error saving config {}: {e}", path.display()))
+    }
+
+    /// Names of the built-in environments followed by the ones of the configured networks
+    pub fn environments(&self) -> Vec<&str> {
+        let networks = self.networks.iter().map(|network| network.name.as_str());
+        ENVIRONMENTS.into_iter().chain(networks).collect()
+    }
+
+    /// Configured network with the name, none for the built-in environments
+    pub fn network(&self, name: &str) -> Option<&NetworkProfile> {
+        self.networks.iter().find(|network| network.name == name)
+    }
+
+    /// How chunks failing to upload are retried
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.retries,
+            initial_delay: Duration::from_secs(self.retry_delay_secs),
+        }
+    }
+
+    /// How long payments of failed uploads are kept
+    pub fn payment_expiry(&self) -> Duration {
+        Duration::from_secs(self.payment_expiry_days.saturating_mul(86400))
+    }
+}
@@ -0,0 +1,231 @@
+//! Streaming self-encryption of files into chunks spooled on disk,
+//! so uploading a file never requires holding it in memory.
+
+use autonomi::data::DataAddress;
+use autonomi::{Bytes, Chunk, XorName};
+use self_encryption::{DataMap, EncryptedChunk};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::error;
+
+/// Counter making spool dirs unique within the process
+static SPOOL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Data map wrapper stored in the data map chunk, same format as autonomi's public data
+#[derive(Serialize, Deserialize)]
+enum DataMapLevel {
+    // Holds the data map to the source data
+    First(DataMap),
+    // Holds the data map of an additional level of chunks, from chunking up a too big data map
+    Additional(DataMap),
+}
+
+/// A file self-encrypted into chunks spooled on disk, ready to be paid for and uploaded.
+/// The spooled chunks are removed on drop.
+#[derive(Debug)]
+pub struct EncryptedFile {
+    data_map_chunk: Chunk,
+    chunks: Vec<(XorName, usize)>,
+    spool_dir: PathBuf,
+    size: u64,
+    content_hash: String,
+}
+
+impl EncryptedFile {
+    /// Self-encrypt the file, keeping at most one chunk in memory at a time
+    pub async fn encrypt(path: &Path) -> Result<Self, String> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || Self::encrypt_blocking(&path))
+            .await
+            .map_err(|e| format!("Encryption task failed: {e}"))?
+    }
+
+    fn encrypt_blocking(path: &Path) -> Result<Self, String> {
+        let size = std::fs::metadata(path)
+            .map_err(|e| format!("Error reading {}: {e}", path.display()))?
+            .len();
+        let content_hash = sha256::try_digest(path)
+            .map_err(|e| format!("Error hashing {}: {e}", path.display()))?;
+        let spool_dir = new_spool_dir()?;
+        let mut file = Self {
+            data_map_chunk: Chunk::new(Bytes::new()),
+            chunks: Vec::new(),
+            spool_dir,
+            size,
+            content_hash,
+        };
+
+        let mut spooled = HashSet::new();
+        let data_map = self_encryption::streaming_encrypt_from_file(path, |name, content| {
+            file.spool_chunk(&mut spooled, name, &content)
+                .map_err(self_encryption::Error::Io)
+        })
+        .map_err(|e| format!("Error encrypting {}: {e}", path.display()))?;
+
+        // Shrink the data map into more chunks until it fits in a single chunk
+        let mut level = DataMapLevel::First(data_map);
+        file.data_map_chunk = loop {
+            let bytes = rmp_serde::to_vec(&level)
+                .map_err(|e| format!("Error serializing data map: {e}"))?;
+            let chunk = Chunk::new(Bytes::from(bytes));
+            if !chunk.is_too_big() {
+                break chunk;
+            }
+
+            let (data_map, chunks) = self_encryption::encrypt(chunk.value().clone())
+                .map_err(|e| format!("Error encrypting data map: {e}"))?;
+            for chunk in chunks {
+                let name = XorName::from_content(&chunk.content);
+                file.spool_chunk(&mut spooled, name, &chunk.content)
+                    .map_err(|e| format!("Error writing chunk to disk: {e}"))?;
+            }
+            level = DataMapLevel::Additional(data_map);
+        };
+
+        let data_map_chunk = &file.data_map_chunk;
+        file.chunks
+            .push((*data_map_chunk.name(), data_map_chunk.size()));
+        Ok(file)
+    }
+
+    fn spool_chunk(
+        &mut self,
+        spooled: &mut HashSet<XorName>,
+        name: XorName,
+        content: &[u8],
+    ) -> std::io::Result<()> {
+        // identical chunks are only stored once
+        if spooled.insert(name) {
+            std::fs::write(self.spool_dir.join(hex_name(&name)), content)?;
+            self.chunks.push((name, content.len()));
+        }
+        Ok(())
+    }
+
+    /// Address of the file on the network, the address of its data map chunk
+    pub fn address(&self) -> DataAddress {
+        DataAddress::new(*self.data_map_chunk.name())
+    }
+
+    /// Hex encoded data map chunk, all it takes to download and decrypt the file
+    pub fn data_map(&self) -> String {
+        hex(self.data_map_chunk.value())
+    }
+
+    /// Leave the data map chunk out of the chunks to upload, so the file can only be
+    /// downloaded by those given its [`EncryptedFile::data_map`]
+    pub fn keep_data_map_private(&mut self) {
+        let name = *self.data_map_chunk.name();
+        self.chunks.retain(|(chunk, _)| *chunk != name);
+    }
+
+    /// Size of the original file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Hex encoded sha256 of the original file
+    pub fn content_hash(&self) -> &str {
+        &self.content_hash
+    }
+
+    /// Names and sizes of all the chunks to upload, the data map chunk included unless kept private
+    pub fn chunks(&self) -> &[(XorName, usize)] {
+        &self.chunks
+    }
+
+    /// Read a chunk back from the spool dir
+    pub fn read_chunk(&self, name: &XorName) -> Result<Chunk, String> {
+        if name == self.data_map_chunk.name() {
+            return Ok(self.data_map_chunk.clone());
+        }
+        let path = self.spool_dir.join(hex_name(name));
+        let content = std::fs::read(&path)
+            .map_err(|e| format!("Error reading chunk {}: {e}", path.display()))?;
+        Ok(Chunk::new(Bytes::from(content)))
+    }
+}
+
+impl Drop for EncryptedFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.spool_dir) {
+            error!(
+                "Error removing spooled chunks {}: {e}",
+                self.spool_dir.display()
+            );
+        }
+    }
+}
+
+/// Decrypt data from its data map chunk, fetching the other chunks with `get_chunk`
+pub(crate) fn decrypt(
+    data_map_chunk: &Chunk,
+    get_chunk: impl Fn(&XorName) -> Option<Bytes>,
+) -> Result<Bytes, String> {
+    let mut level: DataMapLevel = rmp_serde::from_slice(data_map_chunk.value())
+        .map_err(|e| format!("Error reading data map: {e}"))?;
+    loop {
+        let data_map = match &level {
+            DataMapLevel::First(data_map) | DataMapLevel::Additional(data_map) => data_map,
+        };
+        let chunks = data_map
+            .infos()
+            .iter()
+            .map(|info| {
+                get_chunk(&info.dst_hash)
+                    .map(|content| EncryptedChunk { content })
+                    .ok_or_else(|| format!("Missing chunk {}", hex_name(&info.dst_hash)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let bytes = self_encryption::decrypt(data_map, &chunks)
+            .map_err(|e| format!("Error decrypting data: {e}"))?;
+
+        match level {
+            DataMapLevel::First(_) => return Ok(bytes),
+            DataMapLevel::Additional(_) => {
+                level = rmp_serde::from_slice(&bytes)
+                    .map_err(|e| format!("Error reading data map: {e}"))?;
+            }
+        }
+    }
+}
+
+/// Chunks are spooled in the client data dir rather than the temp dir, which may be a small
+/// in-memory filesystem unable to hold large files
+fn new_spool_dir() -> Result<PathBuf, String> {
+    let id = SPOOL_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let dir = crate::cached_payments::get_client_data_dir_path()
+        .map_err(|e| format!("Error getting data dir: {e}"))?
+        .join("spool")
+        .join(format!("{}_{id}", std::process::id()));
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Error creating chunk spool dir {}: {e}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Read back a data map chunk from its hex encoding
+pub(crate) fn data_map_chunk_from_hex(data_map: &str) -> Result<Chunk, String> {
+    let bytes = data_map
+        .trim()
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| "Invalid data map: not hex encoded".to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Chunk::new(Bytes::from(bytes)))
+}
+
+fn hex_name(name: &XorName) -> String {
+    hex(&name.0)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,126 @@
+//! Local history of completed uploads, kept across restarts in the client data dir.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HISTORY_FILE: &str = "ant_upload_history.json";
+
+/// A completed upload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub filename: String,
+    pub size: u64,
+    /// Hex encoded sha256 of the file content, empty for folder archives
+    pub content_hash: String,
+    /// Hex address of the uploaded data, or its hex data map if private
+    pub address: String,
+    pub cost: String,
+    pub environment: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    /// Whether the data map was kept private, anyone given it can download the file
+    #[serde(default)]
+    pub private: bool,
+    /// Whether the file was encrypted with a passphrase, which it takes to download it
+    #[serde(default)]
+    pub passphrase: bool,
+}
+
+impl HistoryEntry {
+    /// Whether the filename or address contains the query, ignoring case
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        self.filename.to_lowercase().contains(&query) || self.address.contains(&query)
+    }
+
+    /// Write the data map of a private upload to `{filename}.datamap` in the directory,
+    /// to share it with whoever should be able to download the file. Returns the path written.
+    pub fn export_data_map(&self, dir: &Path) -> Result<PathBuf, String> {
+        if !self.private {
+            return Err(format!(
+                "{} is public, share its address instead",
+                self.filename
+            ));
+        }
+        let path = dir.join(format!("{}.datamap", self.filename));
+        std::fs::write(&path, &self.address)
+            .map_err(|e| format!("Error exporting data map to {}: {e}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// Upload history saved to a JSON file after every change
+#[derive(Debug, Default)]
+pub struct History {
+    path: PathBuf,
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /// Load the history from the client data dir, next to the cached payments
+    pub fn load() -> Result<Self, String> {
+        let dir = crate::cached_payments::get_client_data_dir_path()
+            .map_err(|e| format!("Error getting data dir: {e}"))?;
+        Self::load_from(dir.join(HISTORY_FILE))
+    }
+
+    /// Load the history from the file, starting empty if it doesn't exist yet
+    pub fn load_from(path: PathBuf) -> Result<Self, String> {
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| format!("Error reading history {}: {e}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Error reading history {}: {e}", path.display())),
+        };
+        Ok(Self { path, entries })
+    }
+
+    /// Entries from oldest to newest
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Record a completed upload, timestamped now if no timestamp is set
+    pub fn add(&mut self, mut entry: HistoryEntry) -> Result<(), String> {
+        if entry.timestamp == 0 {
+            entry.timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+        }
+        self.entries.push(entry);
+        self.save()
+    }
+
+    /// Remove the entry at the index
+    pub fn remove(&mut self, index: usize) -> Result<(), String> {
+        if index < self.entries.len() {
+            self.entries.remove(index);
+        }
+        self.save()
+    }
+
+    /// Latest upload of the content to the environment, if the history has one
+    pub fn find_upload(&self, content_hash: &str, environment: &str) -> Option<&HistoryEntry> {
+        self.entries.iter().rev().find(|entry| {
+            !content_hash.is_empty()
+                && entry.content_hash == content_hash
+                && entry.environment == environment
+        })
+    }
+
+    /// Indices of the entries matching the query, all of them for an empty query
+    pub fn search(&self, query: &str) -> Vec<usize> {
+        (0..self.entries.len())
+            .filter(|&i| self.entries[i].matches(query))
+            .collect()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(&self.entries)
+            .map_err(|e| format!("Error serializing history: {e}"))?;
+        std::fs::write(&self.path, json)
+            .map_err(|e| format!("Error saving history {}: {e}", self.path.display()))
+    }
+}
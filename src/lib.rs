@@ -0,0 +1,24 @@
+//! Upload and download data on the Autonomi Network, reusing cached payments on retries.
+//!
+//! This is the core behind the Ant Upload app, it can be embedded in other tools:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), String> {
+//! let server = ant_upload::Server::new("0x...", ant_upload::DEFAULT_ENVIRONMENT).await?;
+//! let (address, cost) = server.put_data(b"hello world", "hello.txt").await?;
+//! let bytes = server.get_data(&address).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod backend;
+pub mod cached_payments;
+pub mod config;
+pub mod encryption;
+pub mod history;
+pub mod passphrase;
+pub mod secret_key;
+pub mod server;
+pub mod uploads;
+
+pub use server::{DEFAULT_ENVIRONMENT, ENVIRONMENTS, Server};
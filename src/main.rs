@@ -1,84 +1,1024 @@
-pub mod cached_payments;
-mod server;
+mod cli;
 
-use server::DEFAULT_ENVIRONMENT;
-use server::ENVIRONMENTS;
-use server::Server;
+use ant_upload::Server;
+use ant_upload::backend::{AutonomiBackend, StorageBackend, WalletBalance};
+use ant_upload::cached_payments::{self, CachedPayment};
+use ant_upload::config::{Config, LogLevel};
+use ant_upload::history::{History, HistoryEntry};
+use ant_upload::secret_key::SecretKey;
+use ant_upload::uploads::{UploadState, UploadStatus, Uploads};
 
+use clap::Parser;
 use eframe::egui;
 use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+use zeroize::{Zeroize, Zeroizing};
 
 #[derive(Debug, Clone)]
-struct UploadStatus {
-    filename: String,
+struct DownloadStatus {
+    address: String,
     start_time: std::time::Instant,
     completed: bool,
     success: Option<bool>,
     message: String,
-    time_to_complete: Option<f32>, // Store completion time when finished
+    time_to_complete: Option<f32>,
 }
 
-// Define the status update event
-enum UploadEvent {
+enum DownloadEvent {
     Complete {
         index: usize,
-        address: String,
-        cost: String,
+        path: std::path::PathBuf,
+        size: usize,
         duration: std::time::Duration,
-        filename: String,
     },
     Failed {
         index: usize,
-        filename: String,
         duration: std::time::Duration,
         error: String,
     },
 }
 
-struct UploadApp {
-    server: Option<Server>,
+/// What to do with a cached payment
+enum PaymentAction {
+    Retry,
+    Export,
+    Delete,
+}
+
+/// What to do with an unfinished upload
+enum UploadControl {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tab {
+    Upload,
+    Download,
+    History,
+    Payments,
+    Settings,
+}
+
+/// Backend the app logs in to with the key typed in
+trait Connect: StorageBackend {
+    fn connect(
+        secret_key: SecretKey,
+        environment: String,
+        config: Config,
+    ) -> impl Future<Output = Result<Server<Self>, String>> + Send;
+}
+
+impl Connect for AutonomiBackend {
+    async fn connect(
+        secret_key: SecretKey,
+        environment: String,
+        config: Config,
+    ) -> Result<Server, String> {
+        Server::connect(secret_key, &environment, &config).await
+    }
+}
+
+struct UploadApp<B: Connect = AutonomiBackend> {
+    server: Option<Server<B>>,
     dropped_files: Vec<egui::DroppedFile>,
-    upload_statuses: Vec<UploadStatus>, // Track multiple uploads
-    status_receiver: mpsc::UnboundedReceiver<UploadEvent>,
-    status_sender: mpsc::UnboundedSender<UploadEvent>,
+    uploads: Uploads,
+    upload_passphrase: String,
     passcode: String,
     passcode_confirmed: bool,
     selected_env: String,
     error_message: Option<String>,
-    server_init_receiver: Option<mpsc::UnboundedReceiver<Result<Server, String>>>,
+    server_init_receiver: Option<mpsc::UnboundedReceiver<Result<Server<B>, String>>>,
     is_connecting: bool,
+    tab: Tab,
+    download_address: String,
+    download_destination: String,
+    download_passphrase: String,
+    download_statuses: Vec<DownloadStatus>,
+    download_receiver: mpsc::UnboundedReceiver<DownloadEvent>,
+    download_sender: mpsc::UnboundedSender<DownloadEvent>,
+    config: Config,
+    config_message: Option<String>,
+    /// Why the config file failed to load, saving over it is refused until it is fixed
+    config_error: Option<String>,
+    balance: Option<Result<WalletBalance, String>>,
+    balance_receiver: Option<mpsc::UnboundedReceiver<Result<WalletBalance, String>>>,
+    history: Option<History>,
+    history_query: String,
+    history_message: Option<String>,
+    cached_payments: Option<Result<Vec<CachedPayment>, String>>,
+    payments_message: Option<String>,
 }
 
-impl Default for UploadApp {
+impl<B: Connect> Default for UploadApp<B> {
     fn default() -> Self {
-        let (status_sender, status_receiver) = mpsc::unbounded_channel();
+        let (download_sender, download_receiver) = mpsc::unbounded_channel();
+        let download_destination = dirs_next::download_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+        let history = History::load()
+            .map_err(|e| warn!("Upload history disabled: {e}"))
+            .ok();
         Self {
             server: None,
             dropped_files: Vec::new(),
-            upload_statuses: Vec::new(),
-            status_receiver,
-            status_sender,
+            uploads: Uploads::default(),
+            upload_passphrase: String::new(),
             passcode: String::new(),
             passcode_confirmed: false,
-            selected_env: DEFAULT_ENVIRONMENT.to_string(),
+            selected_env: Config::default().environment,
             error_message: None,
             server_init_receiver: None,
             is_connecting: false,
+            tab: Tab::Upload,
+            download_address: String::new(),
+            download_destination,
+            download_passphrase: String::new(),
+            download_statuses: Vec::new(),
+            download_receiver,
+            download_sender,
+            config: Config::default(),
+            config_message: None,
+            config_error: None,
+            balance: None,
+            balance_receiver: None,
+            history,
+            history_query: String::new(),
+            history_message: None,
+            cached_payments: None,
+            payments_message: None,
         }
     }
 }
 
-impl UploadApp {
-    // Add a helper method to check if there are active uploads
-    fn has_active_uploads(&self) -> bool {
-        self.upload_statuses.iter().any(|status| !status.completed)
+impl<B: Connect> UploadApp<B> {
+    /// App using the config loaded at startup, the defaults if it failed to load
+    fn new(config: Result<Config, String>) -> Self {
+        let mut app = Self::default();
+        match config {
+            Ok(config) => {
+                app.selected_env = config.environment.clone();
+                app.uploads.max_concurrent = config.max_concurrent;
+                cached_payments::set_payment_expiration(config.payment_expiry());
+                app.config = config;
+            }
+            Err(e) => {
+                app.error_message = Some(e.clone());
+                app.config_error = Some(e);
+            }
+        }
+        app.load_history_rows();
+        app
+    }
+
+    /// List the uploads recorded in the history for the environment as completed rows.
+    /// Replaces all the rows, only while no upload is running.
+    fn load_history_rows(&mut self) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        self.uploads.statuses = history
+            .entries()
+            .iter()
+            .filter(|entry| entry.environment == self.selected_env)
+            .map(UploadStatus::from_history)
+            .collect();
+    }
+
+    /// Start connecting with the key typed in, moving it out of the field so no copy is left.
+    /// Nothing happens while already connecting or without a key.
+    fn submit_passcode(&mut self) {
+        if self.is_connecting || self.passcode.is_empty() {
+            return;
+        }
+        self.is_connecting = true;
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.server_init_receiver = Some(rx);
+
+        let secret_key = SecretKey::take_from(&mut self.passcode);
+        let env = self.selected_env.clone();
+        let config = self.config.clone();
+        tokio::spawn(async move {
+            let result = B::connect(secret_key, env, config).await;
+            let _ = tx.send(result);
+        });
+    }
+
+    fn has_active_downloads(&self) -> bool {
+        self.download_statuses
+            .iter()
+            .any(|status| !status.completed)
+    }
+
+    fn start_download(&mut self) {
+        let mut address = self.download_address.trim().to_string();
+        if address.is_empty() {
+            self.error_message = Some("Please enter an address to download".to_string());
+            return;
+        }
+
+        // A data map shared as a file is read from it
+        let data_map_file = std::path::Path::new(&address);
+        let mut name = address.chars().take(64).collect::<String>();
+        if data_map_file.is_file() {
+            if let Some(stem) = data_map_file.file_stem() {
+                name = stem.to_string_lossy().to_string();
+            }
+            match std::fs::read_to_string(data_map_file) {
+                Ok(data_map) => address = data_map.trim().to_string(),
+                Err(e) => {
+                    self.error_message = Some(format!("Error reading data map: {e}"));
+                    return;
+                }
+            }
+        }
+
+        // Save into the destination directory under the address name, or to the exact file path
+        let destination = std::path::PathBuf::from(self.download_destination.trim());
+        let path = if destination.is_dir() {
+            destination.join(&name)
+        } else {
+            destination
+        };
+
+        self.error_message = None;
+        self.download_statuses.push(DownloadStatus {
+            address: name,
+            start_time: std::time::Instant::now(),
+            completed: false,
+            success: None,
+            message: format!("Downloading {}...", address),
+            time_to_complete: None,
+        });
+
+        let status_index = self.download_statuses.len() - 1;
+        let download_sender = self.download_sender.clone();
+        let server_clone = self.server.as_ref().unwrap().clone();
+        let passphrase = self.download_passphrase.clone();
+
+        // Spawn async download task
+        tokio::spawn(async move {
+            let start_time = std::time::Instant::now();
+            let bytes = if passphrase.is_empty() {
+                server_clone.get_data(&address).await.and_then(|bytes| {
+                    if ant_upload::passphrase::is_encrypted(&bytes) {
+                        Err(
+                            "The data is protected with a passphrase, enter it to download"
+                                .to_string(),
+                        )
+                    } else {
+                        Ok(bytes)
+                    }
+                })
+            } else {
+                server_clone
+                    .get_data_with_passphrase(&address, &passphrase)
+                    .await
+            };
+            let result = match bytes {
+                Ok(bytes) => tokio::fs::write(&path, &bytes)
+                    .await
+                    .map(|_| bytes.len())
+                    .map_err(|e| format!("Error writing to {}: {e}", path.display())),
+                Err(e) => Err(e),
+            };
+            let event = match result {
+                Ok(size) => DownloadEvent::Complete {
+                    index: status_index,
+                    path,
+                    size,
+                    duration: start_time.elapsed(),
+                },
+                Err(error) => DownloadEvent::Failed {
+                    index: status_index,
+                    duration: start_time.elapsed(),
+                    error,
+                },
+            };
+            let _ = download_sender.send(event);
+        });
+    }
+
+    /// Save a successful upload to the history
+    fn record_upload(&mut self, index: usize) {
+        let (Some(history), Some(status)) = (&mut self.history, self.uploads.statuses.get(index))
+        else {
+            return;
+        };
+        let Some(address) = status.address() else {
+            return;
+        };
+        // content already on the network stays listed under its first upload
+        let content_hash = status.content_hash.clone().unwrap_or_default();
+        if status.already_stored
+            && history
+                .find_upload(&content_hash, &self.selected_env)
+                .is_some()
+        {
+            return;
+        }
+        let entry = HistoryEntry {
+            filename: status.filename.clone(),
+            size: status.size.unwrap_or_default(),
+            content_hash,
+            address: address.to_string(),
+            cost: status.cost.clone().unwrap_or_default(),
+            environment: self.selected_env.clone(),
+            timestamp: 0,
+            private: status.private,
+            passphrase: status.passphrase.is_some() || status.sealed.is_some(),
+        };
+        if let Err(e) = history.add(entry) {
+            error!("{e}");
+            self.error_message = Some(e);
+        }
+    }
+
+    fn show_history_tab(&mut self, ui: &mut egui::Ui) {
+        ui.vertical_centered(|ui| {
+            ui.add_space(20.0);
+            ui.label(egui::RichText::new("Upload History").size(24.0));
+            ui.add_space(20.0);
+            ui.add(
+                egui::TextEdit::singleline(&mut self.history_query)
+                    .hint_text("🔍 search by file name or address")
+                    .desired_width(400.0),
+            );
+            ui.add_space(10.0);
+        });
+
+        let Some(history) = &mut self.history else {
+            ui.colored_label(
+                egui::Color32::from_rgb(220, 50, 50),
+                "Upload history is unavailable, see the logs for details",
+            );
+            return;
+        };
+
+        if let Some(message) = &self.history_message {
+            ui.label(message);
+        }
+
+        let mut removed = None;
+        let mut exported = None;
+        egui::ScrollArea::vertical()
+            .id_source("history")
+            .show(ui, |ui| {
+                // Newest first
+                for index in history.search(&self.history_query).into_iter().rev() {
+                    let entry = &history.entries()[index];
+                    ui.horizontal_wrapped(|ui| {
+                        if ui
+                            .small_button("🗑")
+                            .on_hover_text("Delete from history")
+                            .clicked()
+                        {
+                            removed = Some(index);
+                        }
+                        ui.label(
+                            egui::RichText::new(format!("📁 {}", entry.filename))
+                                .color(egui::Color32::from_gray(180)),
+                        );
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "{} bytes, uploaded {} to {} for {}",
+                                entry.size,
+                                format_age(entry.timestamp),
+                                entry.environment,
+                                entry.cost,
+                            ))
+                            .color(egui::Color32::from_gray(140)),
+                        );
+                        let address = if entry.private {
+                            "🔒 private data map"
+                        } else {
+                            &entry.address
+                        };
+                        if entry.passphrase {
+                            ui.label("🔑").on_hover_text("Encrypted with a passphrase");
+                        }
+                        ui.label(
+                            egui::RichText::new(address)
+                                .color(egui::Color32::from_rgb(180, 120, 255)),
+                        );
+                        if ui.small_button("📋").clicked() {
+                            ui.output_mut(|o| o.copied_text = entry.address.clone());
+                        }
+                        if entry.private
+                            && ui
+                                .small_button("💾")
+                                .on_hover_text("Export the data map to share it")
+                                .clicked()
+                        {
+                            exported = Some(index);
+                        }
+                    });
+                }
+            });
+
+        if let Some(index) = exported {
+            let dir = dirs_next::download_dir().unwrap_or_else(std::env::temp_dir);
+            self.history_message = Some(match history.entries()[index].export_data_map(&dir) {
+                Ok(path) => format!("Exported to {}", path.display()),
+                Err(e) => e,
+            });
+        }
+
+        if let Some(index) = removed {
+            if let Err(e) = history.remove(index) {
+                error!("{e}");
+                self.error_message = Some(e);
+            }
+        }
+    }
+
+    /// Payments made for failed uploads, with actions to retry, export or delete them
+    fn show_payments_tab(&mut self, ui: &mut egui::Ui) {
+        ui.vertical_centered(|ui| {
+            ui.add_space(20.0);
+            ui.label(egui::RichText::new("Cached Payments").size(24.0));
+            ui.add_space(10.0);
+            ui.label(
+                egui::RichText::new(format!(
+                    "Payments of failed uploads are kept for {} days, retry before they expire to not pay twice",
+                    self.config.payment_expiry_days
+                ))
+                .color(egui::Color32::from_gray(180)),
+            );
+            if ui.button("🔄 Refresh").clicked() {
+                self.cached_payments = None;
+            }
+            if let Some(message) = &self.payments_message {
+                ui.label(message);
+            }
+            ui.add_space(10.0);
+        });
+
+        let payments = self.cached_payments.get_or_insert_with(|| {
+            cached_payments::list_cached_payments().map_err(|e| e.to_string())
+        });
+        let payments: Vec<CachedPayment> = match payments {
+            // payments of uploads still going on are kept by them until they finish
+            Ok(payments) => payments
+                .iter()
+                .filter(|payment| {
+                    !self
+                        .uploads
+                        .is_uploading(&payment.key, payment.source.as_deref())
+                })
+                .cloned()
+                .collect(),
+            Err(error) => {
+                ui.colored_label(egui::Color32::from_rgb(220, 50, 50), error.as_str());
+                return;
+            }
+        };
+        if payments.is_empty() {
+            ui.label("No cached payments, all uploads went through!");
+            return;
+        }
+
+        let mut action = None;
+        egui::ScrollArea::vertical()
+            .id_source("cached_payments")
+            .show(ui, |ui| {
+                for payment in &payments {
+                    ui.horizontal_wrapped(|ui| {
+                        let source = payment.source.as_ref().filter(|path| path.is_file());
+                        let can_retry = source.is_some() && self.server.is_some();
+                        if ui
+                            .add_enabled(can_retry, egui::Button::new("⬆ Retry"))
+                            .on_disabled_hover_text(
+                                "The file this payment was made for is unknown or gone",
+                            )
+                            .clicked()
+                        {
+                            action = Some((PaymentAction::Retry, payment.clone()));
+                        }
+                        if ui.small_button("💾").on_hover_text("Export").clicked() {
+                            action = Some((PaymentAction::Export, payment.clone()));
+                        }
+                        if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+                            action = Some((PaymentAction::Delete, payment.clone()));
+                        }
+
+                        // encrypted copies have meaningless names, show the one of the file
+                        let name = match (&payment.source, &payment.filename) {
+                            (Some(_), Some(filename)) if payment.passphrase => {
+                                format!("{filename} (encrypted with a passphrase)")
+                            }
+                            (Some(path), _) => path.display().to_string(),
+                            (None, _) => payment.key.clone(),
+                        };
+                        ui.label(
+                            egui::RichText::new(format!("📁 {name}"))
+                                .color(egui::Color32::from_gray(180)),
+                        );
+                        ui.label(
+                            egui::RichText::new(format!(
+                                "{} chunks paid {}, expires in {} days",
+                                payment.chunks,
+                                format_age(payment.timestamp),
+                                payment.expires_in().as_secs() / 86400,
+                            ))
+                            .color(egui::Color32::from_gray(140)),
+                        );
+                    });
+                }
+            });
+
+        let Some((action, payment)) = action else {
+            return;
+        };
+        let result = match action {
+            PaymentAction::Retry => {
+                // the payment only applies to the upload in the mode it was made for, data
+                // encrypted with a passphrase being retried from its encrypted copy
+                match &self.server {
+                    Some(server) => match self.uploads.retry_payment(server, &payment) {
+                        Ok(_) => {
+                            self.tab = Tab::Upload;
+                            Ok(None)
+                        }
+                        Err(e) => Err(color_eyre::eyre::eyre!(e)),
+                    },
+                    None => Ok(None),
+                }
+            }
+            PaymentAction::Export => {
+                let dir = dirs_next::download_dir().unwrap_or_else(std::env::temp_dir);
+                cached_payments::export_cached_payment(&payment, &dir)
+                    .map(|path| Some(format!("Exported to {}", path.display())))
+            }
+            PaymentAction::Delete => cached_payments::delete_cached_payment(&payment).map(|_| None),
+        };
+        self.payments_message = match result {
+            Ok(message) => message,
+            Err(e) => {
+                error!("{e}");
+                Some(e.to_string())
+            }
+        };
+        self.cached_payments = None;
+    }
+
+    /// Fetch the wallet address and balances in the background
+    fn refresh_balance(&mut self) {
+        let Some(server) = self.server.clone() else {
+            return;
+        };
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.balance_receiver = Some(rx);
+        tokio::spawn(async move {
+            let _ = tx.send(server.balance().await);
+        });
+    }
+
+    /// Why the session can't be left right now, if it can't
+    fn busy_reason(&self) -> Option<&'static str> {
+        if self.uploads.has_active() {
+            Some("Wait for the uploads to finish, or pause them, before leaving the session")
+        } else if self.has_active_downloads() {
+            Some("Wait for the downloads to finish before leaving the session")
+        } else {
+            None
+        }
+    }
+
+    /// Drop the connection and wipe the key, back to the key prompt on the environment.
+    /// Refused while uploads or downloads are active.
+    fn disconnect(&mut self, environment: &str) -> Result<(), String> {
+        if let Some(reason) = self.busy_reason() {
+            return Err(reason.to_string());
+        }
+        info!("Disconnecting from {}", self.selected_env);
+        self.server = None;
+        self.passcode.zeroize();
+        self.passcode_confirmed = false;
+        self.server_init_receiver = None;
+        self.is_connecting = false;
+        self.balance = None;
+        self.balance_receiver = None;
+        self.cached_payments = None;
+        self.error_message = None;
+
+        // paused and finished uploads belong to the session, their payments stay cached on disk
+        let private = self.uploads.private;
+        self.uploads = Uploads::default();
+        self.uploads.private = private;
+        self.selected_env = environment.to_string();
+        self.load_history_rows();
+        self.tab = Tab::Upload;
+        Ok(())
+    }
+
+    /// Menu to leave the session, to log in with another key or on another environment.
+    /// Returns whether the session was left.
+    fn show_session_menu(&mut self, ui: &mut egui::Ui) -> bool {
+        let busy = self.busy_reason();
+        let mut leave = None;
+        ui.menu_button(format!("👤 {}", self.selected_env), |ui| {
+            if let Some(reason) = busy {
+                ui.colored_label(egui::Color32::YELLOW, reason);
+                ui.separator();
+            }
+            ui.add_enabled_ui(busy.is_none(), |ui| {
+                ui.menu_button("🌐 Switch environment", |ui| {
+                    for env in self.config.environments() {
+                        if env != self.selected_env && ui.button(env).clicked() {
+                            leave = Some(env.to_string());
+                            ui.close_menu();
+                        }
+                    }
+                });
+                if ui.button("🔑 Use another key").clicked() {
+                    leave = Some(self.selected_env.clone());
+                    ui.close_menu();
+                }
+                if ui.button("⏏ Disconnect").clicked() {
+                    leave = Some(self.selected_env.clone());
+                    ui.close_menu();
+                }
+            });
+        });
+        let Some(environment) = leave else {
+            return false;
+        };
+        match self.disconnect(&environment) {
+            Ok(()) => true,
+            Err(e) => {
+                self.error_message = Some(e);
+                false
+            }
+        }
+    }
+
+    /// Header showing the wallet paying for uploads
+    fn show_wallet(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .small_button("🔄")
+            .on_hover_text("Refresh balance")
+            .clicked()
+        {
+            self.refresh_balance();
+        }
+        match &self.balance {
+            Some(Ok(balance)) => {
+                ui.label(
+                    egui::RichText::new(format!("{:.6} ETH", balance.eth()))
+                        .color(egui::Color32::from_gray(180)),
+                );
+                ui.label(
+                    egui::RichText::new(format!("{:.6} ANT", balance.ant()))
+                        .color(egui::Color32::from_rgb(100, 200, 100)),
+                );
+                if ui.small_button("📋").clicked() {
+                    ui.output_mut(|o| o.copied_text = balance.address.clone());
+                }
+                ui.label(
+                    egui::RichText::new(&balance.address)
+                        .color(egui::Color32::from_rgb(180, 120, 255)),
+                );
+            }
+            Some(Err(error)) => {
+                ui.colored_label(egui::Color32::from_rgb(220, 50, 50), "Balance unavailable")
+                    .on_hover_text(error);
+            }
+            None => {
+                ui.spinner();
+            }
+        }
+    }
+
+    fn show_settings_tab(&mut self, ui: &mut egui::Ui) {
+        ui.vertical_centered(|ui| {
+            ui.add_space(20.0);
+            ui.label(egui::RichText::new("Settings").size(24.0));
+            ui.add_space(20.0);
+
+            let config = &mut self.config;
+            limit_setting(
+                ui,
+                &mut config.auto_approve_below,
+                "Pay without asking for uploads below",
+            );
+            limit_setting(
+                ui,
+                &mut config.max_upload_cost,
+                "Refuse to pay for uploads above",
+            );
+
+            let mut retries_changed = false;
+            ui.horizontal(|ui| {
+                ui.label("Retry chunks failing to upload");
+                retries_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut config.retries)
+                            .clamp_range(0..=10)
+                            .suffix(" times"),
+                    )
+                    .changed();
+                ui.label("waiting");
+                retries_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut config.retry_delay_secs)
+                            .clamp_range(0..=600)
+                            .suffix(" s"),
+                    )
+                    .changed();
+                ui.label("then twice as long every time, up to 10 minutes");
+            });
+            if retries_changed {
+                if let Some(server) = &mut self.server {
+                    server.set_retry_policy(config.retry_policy());
+                }
+            }
+
+            ui.horizontal(|ui| {
+                ui.label("Upload at most");
+                ui.add(
+                    egui::DragValue::new(&mut config.max_concurrent)
+                        .clamp_range(1..=32)
+                        .suffix(" files at once"),
+                );
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Keep payments of failed uploads for");
+                let expiry = ui.add(
+                    egui::DragValue::new(&mut config.payment_expiry_days)
+                        .clamp_range(30..=365)
+                        .suffix(" days"),
+                );
+                if expiry.changed() {
+                    cached_payments::set_payment_expiration(config.payment_expiry());
+                }
+            });
+
+            let environments: Vec<String> = config
+                .environments()
+                .into_iter()
+                .map(String::from)
+                .collect();
+            ui.horizontal(|ui| {
+                ui.label("Start on the");
+                egui::ComboBox::from_id_source("config_environment")
+                    .selected_text(config.environment.as_str())
+                    .show_ui(ui, |ui| {
+                        for env in environments {
+                            ui.selectable_value(&mut config.environment, env.clone(), env);
+                        }
+                    });
+                ui.label("environment");
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Log level");
+                egui::ComboBox::from_id_source("config_log_level")
+                    .selected_text(config.log_level.as_str())
+                    .show_ui(ui, |ui| {
+                        for level in LogLevel::ALL {
+                            ui.selectable_value(&mut config.log_level, level, level.as_str());
+                        }
+                    });
+                ui.label("applied on restart");
+            });
+
+            ui.add_space(10.0);
+            // saving the defaults over a file that failed to load would lose the user's settings
+            let save = ui
+                .add_enabled(
+                    self.config_error.is_none(),
+                    egui::Button::new("💾 Save settings"),
+                )
+                .on_disabled_hover_text(format!(
+                    "The config file failed to load, fix it and restart to save settings: {}",
+                    self.config_error.as_deref().unwrap_or_default()
+                ));
+            if save.clicked() {
+                self.config_message = Some(match self.config.save() {
+                    Ok(path) => format!("Settings saved to {}", path.display()),
+                    Err(e) => e,
+                });
+            }
+            if let Some(message) = &self.config_message {
+                ui.label(message);
+            }
+        });
+    }
+
+    /// Ask to approve the payment of the first quoted upload waiting for it
+    fn show_payment_confirmation(&mut self, ctx: &egui::Context) {
+        let Some(index) = self.uploads.awaiting_approval() else {
+            return;
+        };
+        let status = &self.uploads.statuses[index];
+        let quote = status.quote.unwrap_or_default();
+        let name = if status.is_archive {
+            format!("the {} folder", status.filename)
+        } else {
+            status.filename.clone()
+        };
+        let gas_saved = status.gas_saved;
+
+        let mut answer = None;
+        egui::Window::new("Confirm payment")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!("Uploading {name} costs:"));
+                ui.add_space(8.0);
+                ui.label(
+                    egui::RichText::new(format!("{:.8} ANT", quote.ant()))
+                        .size(18.0)
+                        .color(egui::Color32::from_rgb(180, 120, 255)),
+                );
+                // the gas price isn't queried, only the gas used is known ahead of paying
+                ui.label(
+                    egui::RichText::new(format!(
+                        "+ roughly {:.8} ETH of gas, estimated at a typical gas price",
+                        quote.eth()
+                    ))
+                    .color(egui::Color32::from_gray(180)),
+                );
+                ui.label(
+                    egui::RichText::new(
+                        "The price is checked again when paying, a higher one is refused",
+                    )
+                    .color(egui::Color32::from_gray(140)),
+                );
+                if let Some(gas_saved) = gas_saved {
+                    ui.label(
+                        egui::RichText::new(format!(
+                            "Paying for all the files at once saves roughly {:.8} ETH of gas",
+                            gas_saved as f64 / 1e18
+                        ))
+                        .color(egui::Color32::from_rgb(100, 200, 100)),
+                    );
+                }
+                if let Some(Ok(balance)) = &self.balance {
+                    ui.add_space(8.0);
+                    ui.label(format!(
+                        "Wallet balance: {:.8} ANT and {:.8} ETH",
+                        balance.ant(),
+                        balance.eth()
+                    ));
+                    if balance.is_short_of(&quote) {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(220, 50, 50),
+                            "Not enough funds in the wallet, this upload is likely to fail",
+                        );
+                    }
+                }
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Pay and upload").clicked() {
+                        answer = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        answer = Some(false);
+                    }
+                });
+            });
+
+        if let Some(approved) = answer {
+            self.uploads.approve(index, approved);
+        }
+    }
+
+    fn show_download_tab(&mut self, ui: &mut egui::Ui) {
+        let available_height = ui.available_height();
+        let half_height = available_height / 2.0;
+
+        ui.vertical_centered(|ui| {
+            ui.add_space(20.0);
+            ui.label(egui::RichText::new("Download from the Network").size(24.0));
+            ui.add_space(20.0);
+
+            egui::Grid::new("download_form")
+                .num_columns(2)
+                .spacing([10.0, 10.0])
+                .show(ui, |ui| {
+                    ui.label("Address");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.download_address)
+                            .hint_text("hex encoded data address, data map or .datamap file")
+                            .desired_width(400.0),
+                    );
+                    ui.end_row();
+
+                    ui.label("Passphrase");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.download_passphrase)
+                            .password(true)
+                            .hint_text("only for data uploaded with a passphrase")
+                            .desired_width(400.0),
+                    );
+                    ui.end_row();
+
+                    ui.label("Save to");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.download_destination)
+                            .hint_text("destination folder or file path")
+                            .desired_width(400.0),
+                    );
+                    ui.end_row();
+                });
+
+            ui.add_space(10.0);
+            let button = ui.add_sized(
+                [120.0, 40.0],
+                egui::Button::new(egui::RichText::new("Download").size(20.0)),
+            );
+            if button.clicked() {
+                self.start_download();
+            }
+
+            // Show error message if any
+            if let Some(error) = &self.error_message {
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::from_rgb(220, 50, 50), error);
+                    if ui.small_button("📋").clicked() {
+                        ui.output_mut(|o| o.copied_text = error.clone());
+                    }
+                });
+            }
+            ui.add_space(20.0);
+        });
+
+        // Scrollable status area
+        egui::ScrollArea::vertical()
+            .id_source("download_statuses")
+            .max_height(half_height)
+            .show(ui, |ui| {
+                for status in &self.download_statuses {
+                    ui.horizontal_wrapped(|ui| {
+                        // Address in purple
+                        ui.label(
+                            egui::RichText::new(format!("📥 {}", status.address))
+                                .color(egui::Color32::from_rgb(180, 120, 255)),
+                        );
+
+                        ui.label(
+                            egui::RichText::new(if !status.completed {
+                                " downloading... "
+                            } else {
+                                " downloaded in "
+                            })
+                            .color(if !status.completed {
+                                egui::Color32::YELLOW
+                            } else {
+                                egui::Color32::from_gray(180)
+                            }),
+                        );
+
+                        // Duration in green
+                        let duration = if let Some(time) = status.time_to_complete {
+                            time
+                        } else {
+                            status.start_time.elapsed().as_secs_f32()
+                        };
+                        ui.label(
+                            egui::RichText::new(format!("{:.2}", duration))
+                                .color(egui::Color32::from_rgb(100, 200, 100)),
+                        );
+
+                        match status.success {
+                            Some(true) => {
+                                ui.label(
+                                    egui::RichText::new(format!(" seconds! {}", status.message))
+                                        .color(egui::Color32::from_gray(180)),
+                                );
+                            }
+                            Some(false) => {
+                                ui.label(
+                                    egui::RichText::new(format!(
+                                        " seconds... Failed to download: {}",
+                                        status.message
+                                    ))
+                                    .color(egui::Color32::RED),
+                                );
+                            }
+                            None => {
+                                ui.label(
+                                    egui::RichText::new(" seconds...")
+                                        .color(egui::Color32::from_gray(180)),
+                                );
+                            }
+                        }
+                    });
+                }
+            });
     }
 }
 
 impl eframe::App for UploadApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Request continuous repaints while uploads are active
-        if self.has_active_uploads() {
+        // Request continuous repaints while uploads or downloads are active
+        if self.uploads.has_active() || self.has_active_downloads() {
             ctx.request_repaint();
         }
 
@@ -136,24 +1076,13 @@ impl eframe::App for UploadApp {
                             let enter_pressed = ui.input(|i| i.key_pressed(egui::Key::Enter));
 
                             if submit_clicked || enter_pressed {
-                                // Start connection attempt
-                                self.is_connecting = true;
-                                let (tx, rx) = mpsc::unbounded_channel();
-                                self.server_init_receiver = Some(rx);
-
-                                let passcode = self.passcode.clone();
-                                let env = self.selected_env.clone();
-
-                                // Spawn async task for server initialization
-                                tokio::spawn(async move {
-                                    let result = Server::new(&passcode, &env).await;
-                                    let _ = tx.send(result);
-                                });
+                                self.submit_passcode();
+                                forget_text_edit_history(ui.ctx(), response.id);
                             }
 
                             // help message
                             ui.add_space(10.0);
-                            ui.label(egui::RichText::new("Please enter a valid EVM hex encoded private key. Your Secret Key is used solely for local transaction signing and is NEVER stored, transmitted, or persisted. The key exists ONLY in memory until the app is closed. You must own ANT and some ETH to upload data. Stop reading and start uploading!").size(10.0));
+                            ui.label(egui::RichText::new("Please enter a valid EVM hex encoded private key. Your Secret Key is used solely for local transaction signing and is NEVER stored, transmitted, or persisted. The key exists ONLY in memory, cleared from this field once submitted and wiped when you disconnect or close the app. You must own ANT and some ETH to upload data. Stop reading and start uploading!").size(10.0));
 
                             // Check for server init result
                             if let Some(receiver) = &mut self.server_init_receiver {
@@ -163,13 +1092,15 @@ impl eframe::App for UploadApp {
 
                                     match result {
                                         Ok(server) => {
-                                            self.server = Some(server);
+                                            let retry_policy = self.config.retry_policy();
+                                            self.server =
+                                                Some(server.with_retry_policy(retry_policy));
                                             self.passcode_confirmed = true;
                                             self.error_message = None;
+                                            self.refresh_balance();
                                         }
                                         Err(error) => {
                                             self.error_message = Some(error);
-                                            self.passcode.clear();
                                         }
                                     }
                                 }
@@ -179,6 +1110,7 @@ impl eframe::App for UploadApp {
                 });
 
             // Environment selector window (always visible)
+            let mut selected = None;
             egui::Window::new("env_selector")
                 .frame(egui::Frame::none())
                 .fixed_pos(egui::pos2(
@@ -187,54 +1119,119 @@ impl eframe::App for UploadApp {
                 ))
                 .title_bar(false)
                 .show(ctx, |ui| {
-                    let environments = ENVIRONMENTS;
+                    let environments = self.config.environments();
                     for env in environments.iter().rev() {
                         let is_selected = self.selected_env == *env;
                         if ui.selectable_label(is_selected, *env).clicked() {
-                            self.selected_env = env.to_string();
-                            println!("Selected environment: {}", env);
+                            selected = Some(env.to_string());
                         }
                     }
                 });
+            if let Some(env) = selected {
+                info!("Selected environment: {}", env);
+                self.selected_env = env;
+                self.load_history_rows();
+            }
             return; // Don't show main UI until passcode is confirmed
         }
 
-        // Process any completed uploads
-        while let Ok(event) = self.status_receiver.try_recv() {
+        // Process any completed uploads, never paying more than the balance without asking
+        let balance = match &self.balance {
+            Some(Ok(balance)) => balance.ant(),
+            _ => f64::INFINITY,
+        };
+        self.uploads.auto_approve_below = self
+            .config
+            .auto_approve_below
+            .map(|below| below.min(balance));
+        self.uploads.max_upload_cost = self.config.max_upload_cost;
+        self.uploads.max_concurrent = self.config.max_concurrent;
+        let completed = self.uploads.process_events();
+        self.show_payment_confirmation(ctx);
+
+        // Record completed uploads and refresh the balance they were paid from
+        for index in &completed {
+            self.record_upload(*index);
+        }
+        if !completed.is_empty() {
+            self.refresh_balance();
+            self.cached_payments = None;
+        }
+        if let Some(receiver) = &mut self.balance_receiver {
+            if let Ok(result) = receiver.try_recv() {
+                self.balance_receiver = None;
+                self.balance = Some(result);
+            }
+        }
+
+        // Process any completed downloads
+        while let Ok(event) = self.download_receiver.try_recv() {
             match event {
-                UploadEvent::Complete {
+                DownloadEvent::Complete {
                     index,
-                    address,
-                    cost,
+                    path,
+                    size,
                     duration,
-                    filename,
                 } => {
-                    if let Some(status) = self.upload_statuses.get_mut(index) {
+                    if let Some(status) = self.download_statuses.get_mut(index) {
                         status.completed = true;
                         status.success = Some(true);
                         status.time_to_complete = Some(duration.as_secs_f32());
-                        status.message = format!(
-                            "{filename} was successfully uploaded in {} seconds for {cost}! At address: {address}",
-                            duration.as_secs_f32(),
-                        );
+                        status.message = format!("Saved {size} bytes to {}", path.display());
                     }
                 }
-                UploadEvent::Failed {
+                DownloadEvent::Failed {
                     index,
-                    filename,
                     duration,
                     error,
                 } => {
-                    if let Some(status) = self.upload_statuses.get_mut(index) {
+                    if let Some(status) = self.download_statuses.get_mut(index) {
                         status.completed = true;
                         status.success = Some(false);
                         status.time_to_complete = Some(duration.as_secs_f32());
-                        status.message = format!("Failed to upload {}: {}", filename, error);
+                        status.message = error;
                     }
                 }
             }
         }
 
+        let mut disconnected = false;
+        egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.tab, Tab::Upload, "⬆ Upload");
+                ui.selectable_value(&mut self.tab, Tab::Download, "⬇ Download");
+                ui.selectable_value(&mut self.tab, Tab::History, "🕘 History");
+                ui.selectable_value(&mut self.tab, Tab::Payments, "💳 Payments");
+                ui.selectable_value(&mut self.tab, Tab::Settings, "⚙ Settings");
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    disconnected = self.show_session_menu(ui);
+                    self.show_wallet(ui);
+                });
+            });
+        });
+        // the rest of the frame expects a server, the login screen shows from the next one
+        if disconnected {
+            ctx.request_repaint();
+            return;
+        }
+
+        if self.tab == Tab::Download {
+            egui::CentralPanel::default().show(ctx, |ui| self.show_download_tab(ui));
+            return;
+        }
+        if self.tab == Tab::History {
+            egui::CentralPanel::default().show(ctx, |ui| self.show_history_tab(ui));
+            return;
+        }
+        if self.tab == Tab::Payments {
+            egui::CentralPanel::default().show(ctx, |ui| self.show_payments_tab(ui));
+            return;
+        }
+        if self.tab == Tab::Settings {
+            egui::CentralPanel::default().show(ctx, |ui| self.show_settings_tab(ui));
+            return;
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             // Calculate half screen height
             let available_height = ui.available_height();
@@ -282,7 +1279,19 @@ impl eframe::App for UploadApp {
                     stroke,
                 );
 
-                ui.label(egui::RichText::new("Drop files here to upload").size(24.0));
+                ui.label(egui::RichText::new("Drop files or folders here to upload").size(24.0));
+                ui.checkbox(
+                    &mut self.uploads.private,
+                    "🔒 Private: keep the data map, only those you share it with can download",
+                );
+                ui.horizontal(|ui| {
+                    ui.label("🔑 Passphrase");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.upload_passphrase)
+                            .password(true)
+                            .hint_text("optional, needed to download the files"),
+                    );
+                });
 
                 ui.add_space(10.0);
                 // Show error message if any
@@ -298,12 +1307,32 @@ impl eframe::App for UploadApp {
             });
 
             // Bottom half - Scrollable status area
+            let mut retry = None;
+            let mut reorder = None;
+            let mut control = None;
+            let queued = self.uploads.queued();
             egui::ScrollArea::vertical()
                 .max_height(half_height)
                 .show(ui, |ui| {
-                    // Display upload statuses
-                    for status in &self.upload_statuses {
+                    // Display upload statuses, files dropped as a folder grouped under it
+                    let mut current_group = None;
+                    for index in self.uploads.display_order() {
+                        let status = &self.uploads.statuses[index];
+                        if status.group != current_group {
+                            current_group = status.group;
+                            let name = current_group.and_then(|g| self.uploads.group_name(g));
+                            if let Some(name) = name {
+                                ui.label(
+                                    egui::RichText::new(format!("📂 {name}"))
+                                        .color(egui::Color32::from_gray(220)),
+                                );
+                            }
+                        }
+
                         ui.horizontal(|ui| {
+                            if status.group.is_some() {
+                                ui.add_space(20.0);
+                            }
                             ui.with_layout(
                                 egui::Layout::left_to_right(egui::Align::Center)
                                     .with_main_wrap(true),
@@ -311,20 +1340,55 @@ impl eframe::App for UploadApp {
                                     ui.set_max_width(ui.available_width() - 30.0);
 
                                     // Filename in grey
+                                    let name = if status.is_archive {
+                                        format!("🗄 archive of {}", status.filename)
+                                    } else if status.is_batch {
+                                        format!("💳 payment for {}", status.filename)
+                                    } else if status.group.is_some() {
+                                        format!("📄 {}", status.filename)
+                                    } else {
+                                        format!("📁 {}", status.filename)
+                                    };
                                     ui.label(
-                                        egui::RichText::new(format!("📁 {}", status.filename))
+                                        egui::RichText::new(name)
                                             .color(egui::Color32::from_gray(180)),
                                     );
 
+                                    // Queued uploads show their place in line until they start
+                                    if status.state == UploadState::Queued {
+                                        let text = match queued.iter().position(|&i| i == index) {
+                                            Some(position) => {
+                                                format!(" queued, {} in line", position + 1)
+                                            }
+                                            None => " queued".to_string(),
+                                        };
+                                        ui.label(
+                                            egui::RichText::new(text)
+                                                .color(egui::Color32::from_gray(140)),
+                                        );
+                                        return;
+                                    }
+                                    if status.state == UploadState::Paused {
+                                        ui.label(
+                                            egui::RichText::new(" paused")
+                                                .color(egui::Color32::from_gray(140)),
+                                        );
+                                        return;
+                                    }
+
                                     // Status text and duration
                                     ui.label(
-                                        egui::RichText::new(if !status.completed {
+                                        egui::RichText::new(if !status.is_done() {
                                             " uploading... "
+                                        } else if status.is_batch {
+                                            " paid in "
+                                        } else if status.uploaded_at.is_some() {
+                                            " uploaded "
                                         } else {
                                             " uploaded in "
                                         })
                                         .color(
-                                            if !status.completed {
+                                            if !status.is_done() {
                                                 egui::Color32::YELLOW
                                             } else {
                                                 egui::Color32::from_gray(180)
@@ -338,25 +1402,61 @@ impl eframe::App for UploadApp {
                                     } else {
                                         status.start_time.elapsed().as_secs_f32() // Live counter
                                     };
+                                    let duration = match status.uploaded_at {
+                                        Some(timestamp) => format_age(timestamp),
+                                        None => format!("{:.2}", duration),
+                                    };
                                     ui.label(
-                                        egui::RichText::new(format!("{:.2}", duration))
+                                        egui::RichText::new(duration)
                                             .color(egui::Color32::from_rgb(100, 200, 100)),
                                     );
 
-                                    // Only show address for completed successful uploads
-                                    if status.completed && status.success == Some(true) {
-                                        // "seconds! At address:" in grey
+                                    // Files paid for together show the gas saved instead of an address
+                                    if status.is_batch && status.success == Some(true) {
+                                        let saved = status.gas_saved.unwrap_or_default();
                                         ui.label(
-                                            egui::RichText::new(" seconds! At address: ")
+                                            egui::RichText::new(format!(
+                                                " seconds! Saved roughly {:.8} ETH of gas",
+                                                saved as f64 / 1e18
+                                            ))
+                                            .color(egui::Color32::from_gray(180)),
+                                        );
+                                    } else if status.is_done() && status.success == Some(true) {
+                                        // Only show address for completed successful uploads
+                                        // "seconds! At address:" in grey, with the cost saved
+                                        // when the file was already on the network
+                                        let text = if status.already_stored {
+                                            let saved = status
+                                                .content_hash
+                                                .as_deref()
+                                                .zip(self.history.as_ref())
+                                                .and_then(|(hash, history)| {
+                                                    history.find_upload(hash, &self.selected_env)
+                                                })
+                                                .map(|entry| format!(", saved {}", entry.cost))
+                                                .unwrap_or_default();
+                                            format!(
+                                                " seconds! Already on the network{saved}. At address: "
+                                            )
+                                        } else if status.uploaded_at.is_some() {
+                                            ", at address: ".to_string()
+                                        } else {
+                                            " seconds! At address: ".to_string()
+                                        };
+                                        ui.label(
+                                            egui::RichText::new(text)
                                                 .color(egui::Color32::from_gray(180)),
                                         );
 
-                                        // Address in purple, make sure to match the case in the status message
-                                        if let Some(address) =
-                                            status.message.split("At address: ").nth(1)
-                                        {
+                                        // Address in purple, private data maps are only copied
+                                        if status.private {
                                             ui.label(
-                                                egui::RichText::new(address.trim())
+                                                egui::RichText::new("🔒 private data map")
+                                                    .color(egui::Color32::from_rgb(180, 120, 255)),
+                                            );
+                                        } else if let Some(address) = status.address() {
+                                            ui.label(
+                                                egui::RichText::new(address)
                                                     .color(egui::Color32::from_rgb(180, 120, 255)),
                                             );
                                         }
@@ -374,24 +1474,105 @@ impl eframe::App for UploadApp {
                                             egui::RichText::new(" seconds...")
                                                 .color(egui::Color32::from_gray(180)),
                                         );
+
+                                        // Progress bar once the file is encrypted into chunks
+                                        if let Some(progress) = &status.progress {
+                                            let text = if status.awaiting_approval {
+                                                "waiting for payment approval".to_string()
+                                            } else if progress.paid < progress.total {
+                                                if progress.quoted < progress.total {
+                                                    format!("quoting {} chunks", progress.total)
+                                                } else {
+                                                    format!("paying for {} chunks", progress.total)
+                                                }
+                                            } else if let Some(eta) = status.eta() {
+                                                format!(
+                                                    "{}/{} chunks stored, {:.0}s left",
+                                                    progress.stored,
+                                                    progress.total,
+                                                    eta.as_secs_f32()
+                                                )
+                                            } else {
+                                                format!(
+                                                    "{}/{} chunks stored",
+                                                    progress.stored, progress.total
+                                                )
+                                            };
+                                            ui.add(
+                                                egui::ProgressBar::new(progress.fraction())
+                                                    .desired_width(250.0)
+                                                    .text(text),
+                                            );
+                                        }
                                     }
                                 },
                             );
 
                             // Only show copy button for completed successful uploads with an address
-                            if status.completed && status.success == Some(true) {
-                                if let Some(address) = status.message.split("At address: ").nth(1) {
-                                    if !address.trim().is_empty() && ui.small_button("📋").clicked()
-                                    {
-                                        ui.output_mut(|o| {
-                                            o.copied_text = address.trim().to_string()
-                                        });
+                            if let Some(address) = status.address() {
+                                if ui.small_button("📋").clicked() {
+                                    ui.output_mut(|o| o.copied_text = address.to_string());
+                                }
+                            }
+
+                            // Failed uploads can be retried in place, reusing their payment
+                            if status.success == Some(false) && status.source.is_some() {
+                                if ui.small_button("🔁").on_hover_text("Retry").clicked() {
+                                    retry = Some(index);
+                                }
+                            }
+
+                            // Single files, whole groups and files paid for at once can be paused or cancelled
+                            if self.uploads.controllable(index) && !status.is_done() {
+                                if status.state == UploadState::Paused {
+                                    if ui.small_button("▶").on_hover_text("Resume").clicked() {
+                                        control = Some((index, UploadControl::Resume));
                                     }
+                                } else if ui.small_button("⏸").on_hover_text("Pause").clicked() {
+                                    control = Some((index, UploadControl::Pause));
+                                }
+                                if ui.small_button("✖").on_hover_text("Cancel").clicked() {
+                                    control = Some((index, UploadControl::Cancel));
+                                }
+                            }
+
+                            // Queued uploads can be moved up or down the queue
+                            if let Some(position) = queued.iter().position(|&i| i == index) {
+                                if position > 0 && ui.small_button("⏶").clicked() {
+                                    reorder = Some((index, position - 1));
+                                }
+                                if position + 1 < queued.len() && ui.small_button("⏷").clicked() {
+                                    reorder = Some((index, position + 1));
                                 }
                             }
                         });
                     }
                 });
+
+            if let Some((index, position)) = reorder {
+                self.uploads.move_queued(index, position);
+            }
+
+            match control {
+                Some((index, UploadControl::Pause)) => self.uploads.pause(index),
+                Some((index, UploadControl::Cancel)) => self.uploads.cancel(index),
+                Some((index, UploadControl::Resume)) => {
+                    let server = self.server.as_ref().unwrap();
+                    if let Err(e) = self.uploads.resume(server, index) {
+                        error!("{e}");
+                        self.error_message = Some(e);
+                    }
+                }
+                None => {}
+            }
+
+            if let Some(index) = retry {
+                let server = self.server.as_ref().unwrap();
+                if let Err(e) = self.uploads.retry(server, index) {
+                    error!("{e}");
+                    self.error_message = Some(e);
+                }
+            }
         });
 
         // Handle file drops
@@ -399,64 +1580,25 @@ impl eframe::App for UploadApp {
             if !i.raw.dropped_files.is_empty() {
                 self.dropped_files = i.raw.dropped_files.clone();
 
-                // Process each dropped file
+                // Process each dropped file, files dropped together are paid for at once
+                let server = self.server.as_ref().unwrap();
+                self.uploads.passphrase =
+                    Some(Zeroizing::new(self.upload_passphrase.clone())).filter(|p| !p.is_empty());
+                let mut files = Vec::new();
                 for file in &self.dropped_files {
                     if let Some(path) = &file.path {
-                        match std::fs::read(path) {
-                            Ok(bytes) => {
-                                let filename = path
-                                    .file_name()
-                                    .and_then(|n| n.to_str())
-                                    .unwrap_or("unknown")
-                                    .to_string();
-
-                                // Create new upload status
-                                self.upload_statuses.push(UploadStatus {
-                                    filename: filename.clone(),
-                                    start_time: std::time::Instant::now(),
-                                    completed: false,
-                                    success: None,
-                                    message: format!("Uploading {}...", filename),
-                                    time_to_complete: None,
-                                });
-
-                                let status_index = self.upload_statuses.len() - 1;
-                                let status_sender = self.status_sender.clone();
-                                let filename_clone = filename.clone();
-
-                                let server_clone = self.server.as_ref().unwrap().clone();
-                                // Spawn async upload task
-                                tokio::spawn(async move {
-                                    let start_time = std::time::Instant::now();
-                                    match server_clone.put_data(&bytes, &filename).await {
-                                        Ok((address, cost)) => {
-                                            let _ = status_sender.send(UploadEvent::Complete {
-                                                index: status_index,
-                                                address: address.clone(),
-                                                cost: cost.clone(),
-                                                duration: start_time.elapsed(),
-                                                filename: filename_clone,
-                                            });
-                                        }
-                                        Err(e) => {
-                                            let _ = status_sender.send(UploadEvent::Failed {
-                                                index: status_index,
-                                                filename: filename_clone,
-                                                duration: start_time.elapsed(),
-                                                error: e.to_string(),
-                                            });
-                                        }
-                                    }
-                                });
-                            }
-                            Err(e) => {
-                                println!("Error reading file {}: {e}", path.display());
-                                self.error_message =
-                                    Some(format!("Error reading file {}: {e}", path.display()));
+                        // Folders are uploaded file by file then shared as one archive
+                        if path.is_dir() {
+                            if let Err(e) = self.uploads.start_folder(server, path) {
+                                error!("{e}");
+                                self.error_message = Some(e);
                             }
+                        } else {
+                            files.push(path.clone());
                         }
                     }
                 }
+                self.uploads.start_files(server, &files);
             }
         });
     }
@@ -464,6 +1606,21 @@ impl eframe::App for UploadApp {
 
 #[tokio::main]
 async fn main() -> eframe::Result<()> {
+    // Run headless when a command is given, e.g. `ant_upload put <files...>`
+    let args = cli::Args::parse();
+    let config = Config::load().map_err(|e| {
+        eprintln!("{e}");
+        e
+    });
+    init_logging(config.as_ref().map(|c| c.log_level).unwrap_or_default());
+    if let Some(command) = args.command {
+        // running with the defaults would drop the limits set in the broken file
+        let Ok(config) = config else {
+            std::process::exit(1);
+        };
+        std::process::exit(cli::run(command, &config).await);
+    }
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_icon(load_icon()),
         ..Default::default()
@@ -472,10 +1629,65 @@ async fn main() -> eframe::Result<()> {
     eframe::run_native(
         "Ant Upload",
         options,
-        Box::new(|_cc| Box::new(UploadApp::default())),
+        Box::new(|_cc| Box::new(UploadApp::new(config))),
     )
 }
 
+/// Print the logs of the app and the network client up to the level to stderr, keeping stdout
+/// for the output of commands
+fn init_logging(level: LogLevel) {
+    use tracing_subscriber::filter::LevelFilter;
+    let filter = match level {
+        LogLevel::Off => LevelFilter::OFF,
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// Reset the state of the text edit, whose undo history keeps copies of the text typed in
+fn forget_text_edit_history(ctx: &egui::Context, id: egui::Id) {
+    egui::TextEdit::store_state(ctx, id, egui::text_edit::TextEditState::default());
+}
+
+/// Optional limit in ANT, set with a checkbox
+fn limit_setting(ui: &mut egui::Ui, limit: &mut Option<f64>, label: &str) {
+    ui.horizontal(|ui| {
+        let mut enabled = limit.is_some();
+        let mut ant = limit.unwrap_or(0.01);
+        ui.checkbox(&mut enabled, label);
+        ui.add_enabled(
+            enabled,
+            egui::DragValue::new(&mut ant)
+                .speed(0.001)
+                .clamp_range(0.0..=f64::MAX)
+                .suffix(" ANT"),
+        );
+        *limit = enabled.then_some(ant);
+    });
+}
+
+/// How long ago the unix timestamp was, e.g. "3 hours ago"
+fn format_age(timestamp: u64) -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let secs = now.saturating_sub(timestamp);
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} minutes ago", secs / 60),
+        3600..86400 => format!("{} hours ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
 fn load_icon() -> egui::IconData {
     let image_bytes = include_bytes!("../assets/ant_up.png");
     let decoded = image::load_from_memory(image_bytes)
@@ -489,3 +1701,58 @@ fn load_icon() -> egui::IconData {
         height: 1024,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ant_upload::backend::MemoryBackend;
+
+    impl Connect for MemoryBackend {
+        async fn connect(
+            secret_key: SecretKey,
+            environment: String,
+            _config: Config,
+        ) -> Result<Server<Self>, String> {
+            Server::init(secret_key.expose(), &environment).await
+        }
+    }
+
+    fn app_with_key(key: &str) -> UploadApp<MemoryBackend> {
+        let dir = std::env::temp_dir().join(format!("ant_upload_app_{}", std::process::id()));
+        cached_payments::set_client_data_dir(dir);
+        UploadApp {
+            passcode: key.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn submitted_key_is_cleared_from_the_login_field() {
+        let mut app = app_with_key("0xdeadbeef");
+
+        app.submit_passcode();
+        assert!(app.passcode.is_empty());
+        assert!(app.is_connecting);
+        let receiver = app.server_init_receiver.as_mut().expect("connecting");
+        assert!(receiver.recv().await.expect("connection result").is_ok());
+    }
+
+    #[tokio::test]
+    async fn key_is_not_submitted_again_while_connecting() {
+        let mut app = app_with_key("0xdeadbeef");
+        app.submit_passcode();
+
+        // a second Enter keeps the key typed in meanwhile and the first connection
+        app.passcode = "0xc0ffee".to_string();
+        app.submit_passcode();
+        assert_eq!(app.passcode, "0xc0ffee");
+        let receiver = app.server_init_receiver.as_mut().expect("connecting");
+        assert!(receiver.recv().await.expect("connection result").is_ok());
+
+        // nor is an empty field submitted
+        let mut app = app_with_key("");
+        app.submit_passcode();
+        assert!(!app.is_connecting);
+        assert!(app.server_init_receiver.is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! Encryption of data with a passphrase before it is uploaded, on top of self-encryption.
+//!
+//! Encrypted data starts with a header recording how it was encrypted:
+//! `ANTENC`, the format version, the algorithm, the Argon2 memory, time and parallelism costs
+//! as little endian u32s, the salt and the nonce prefix. It is followed by the ciphertext in
+//! segments of [`SEGMENT_LEN`] bytes each with its tag, encrypted with the STREAM construction
+//! so files never have to be held in memory.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{KeyInit, OsRng};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::error;
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8; 6] = b"ANTENC";
+const VERSION: u8 = 1;
+/// Key derived with Argon2id, data encrypted with XChaCha20-Poly1305 in STREAM segments
+const ARGON2ID_XCHACHA20POLY1305: u8 = 1;
+const SALT_LEN: usize = 16;
+/// XChaCha20 nonce less the segment counter and last segment flag appended by STREAM
+const NONCE_LEN: usize = 19;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 3 * 4 + SALT_LEN + NONCE_LEN;
+/// Plaintext bytes per segment, the last segment holding the rest
+const SEGMENT_LEN: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// Encrypted copies in use by a [`SealedFile`], kept when their cached payment is removed
+static OPEN_SEALED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Whether the data starts with the header of data encrypted with a passphrase
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypt the data with a key derived from the passphrase, with a random salt and nonce
+pub fn encrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    let mut sealed = Vec::with_capacity(HEADER_LEN + data.len() + TAG_LEN);
+    encrypt_stream(data, &mut sealed, passphrase)?;
+    Ok(sealed)
+}
+
+/// Encrypt what is read from the reader into the writer, one segment at a time
+fn encrypt_stream(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    passphrase: &str,
+) -> Result<(), String> {
+    let params = Params::default();
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let cipher = cipher(passphrase, &salt, params.clone())?;
+    let mut encryptor = EncryptorBE32::from_aead(cipher, (&nonce).into());
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(VERSION);
+    header.push(ARGON2ID_XCHACHA20POLY1305);
+    header.extend_from_slice(&params.m_cost().to_le_bytes());
+    header.extend_from_slice(&params.t_cost().to_le_bytes());
+    header.extend_from_slice(&params.p_cost().to_le_bytes());
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&nonce);
+    write_all(&mut writer, &header)?;
+
+    // a segment is only known to be the last once the next one is read empty
+    let mut segment = read_segment(&mut reader)?;
+    loop {
+        let next = read_segment(&mut reader)?;
+        if next.is_empty() {
+            let ciphertext = encryptor
+                .encrypt_last(segment.as_slice())
+                .map_err(|e| format!("Error encrypting data: {e}"))?;
+            write_all(&mut writer, &ciphertext)?;
+            return writer
+                .flush()
+                .map_err(|e| format!("Error writing encrypted data: {e}"));
+        }
+        let ciphertext = encryptor
+            .encrypt_next(segment.as_slice())
+            .map_err(|e| format!("Error encrypting data: {e}"))?;
+        write_all(&mut writer, &ciphertext)?;
+        segment = next;
+    }
+}
+
+fn read_segment(reader: &mut impl Read) -> Result<Vec<u8>, String> {
+    let mut segment = Vec::with_capacity(SEGMENT_LEN);
+    reader
+        .take(SEGMENT_LEN as u64)
+        .read_to_end(&mut segment)
+        .map_err(|e| format!("Error reading data to encrypt: {e}"))?;
+    Ok(segment)
+}
+
+fn write_all(writer: &mut impl Write, bytes: &[u8]) -> Result<(), String> {
+    writer
+        .write_all(bytes)
+        .map_err(|e| format!("Error writing encrypted data: {e}"))
+}
+
+/// Decrypt data encrypted by [`encrypt`], failing if the passphrase is wrong or the data altered
+pub fn decrypt(sealed: &[u8], passphrase: &str) -> Result<Vec<u8>, String> {
+    if !is_encrypted(sealed) || sealed.len() < HEADER_LEN {
+        return Err("Data is not encrypted with a passphrase".to_string());
+    }
+    let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+    let (version, algorithm) = (header[MAGIC.len()], header[MAGIC.len() + 1]);
+    if version != VERSION || algorithm != ARGON2ID_XCHACHA20POLY1305 {
+        return Err(format!(
+            "Unsupported encryption: version {version}, algorithm {algorithm}"
+        ));
+    }
+
+    let mut fields = header[MAGIC.len() + 2..].chunks(4);
+    let mut cost = || {
+        let bytes = fields.next().and_then(|bytes| bytes.try_into().ok());
+        u32::from_le_bytes(bytes.unwrap_or_default())
+    };
+    let (m_cost, t_cost, p_cost) = (cost(), cost(), cost());
+    // the costs come from the data, bound them so a crafted header can't exhaust memory or time
+    if m_cost > Params::DEFAULT_M_COST
+        || t_cost > Params::DEFAULT_T_COST
+        || p_cost > Params::DEFAULT_P_COST
+    {
+        return Err(format!(
+            "Key derivation costs too high: memory {m_cost} KiB, {t_cost} passes, {p_cost} lanes"
+        ));
+    }
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32))
+        .map_err(|e| format!("Invalid key derivation parameters: {e}"))?;
+    let salt_start = HEADER_LEN - SALT_LEN - NONCE_LEN;
+    let salt = &header[salt_start..salt_start + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let cipher = cipher(passphrase, salt, params)?;
+    let mut decryptor = DecryptorBE32::from_aead(cipher, nonce.into());
+    let wrong_passphrase = |_| "Wrong passphrase or corrupted data".to_string();
+    let segments: Vec<&[u8]> = ciphertext.chunks(SEGMENT_LEN + TAG_LEN).collect();
+    // empty data still has a last segment with its tag
+    let Some((last, segments)) = segments.split_last() else {
+        return Err("Wrong passphrase or corrupted data".to_string());
+    };
+    let mut data = Vec::with_capacity(ciphertext.len());
+    for segment in segments {
+        data.extend(decryptor.decrypt_next(*segment).map_err(wrong_passphrase)?);
+    }
+    data.extend(decryptor.decrypt_last(*last).map_err(wrong_passphrase)?);
+    Ok(data)
+}
+
+fn cipher(passphrase: &str, salt: &[u8], params: Params) -> Result<XChaCha20Poly1305, String> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| format!("Error deriving key from passphrase: {e}"))?;
+    XChaCha20Poly1305::new_from_slice(key.as_ref()).map_err(|e| format!("Invalid key: {e}"))
+}
+
+/// Copy of a file encrypted with a passphrase, to upload in place of the file.
+/// The copy is kept in the client data dir while a cached payment was made for it, so the paid
+/// upload can be retried with the same bytes, and removed once neither uses it anymore.
+#[derive(Debug)]
+pub struct SealedFile {
+    path: PathBuf,
+}
+
+impl SealedFile {
+    /// Encrypt the file into a copy, streaming it through the cipher
+    pub async fn seal(source: &Path, passphrase: &str) -> Result<Self, String> {
+        let source = source.to_path_buf();
+        let passphrase = Zeroizing::new(passphrase.to_string());
+        tokio::task::spawn_blocking(move || Self::seal_blocking(&source, &passphrase))
+            .await
+            .map_err(|e| format!("Encryption task failed: {e}"))?
+    }
+
+    fn seal_blocking(source: &Path, passphrase: &str) -> Result<Self, String> {
+        let reader = std::fs::File::open(source)
+            .map_err(|e| format!("Error reading {}: {e}", source.display()))?;
+
+        let dir = sealed_dir()?;
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("Error creating {}: {e}", dir.display()))?;
+        let path = dir.join(format!("{:016x}", OsRng.next_u64()));
+        let writer = std::fs::File::create(&path)
+            .map_err(|e| format!("Error writing {}: {e}", path.display()))?;
+        // removes the partial copy if encrypting fails
+        let sealed = Self::track(path);
+        encrypt_stream(BufReader::new(reader), BufWriter::new(writer), passphrase)
+            .map_err(|e| format!("Error sealing {}: {e}", source.display()))?;
+        Ok(sealed)
+    }
+
+    /// Use a copy sealed by an earlier upload, e.g. to retry the upload with its cached payment
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if !path.starts_with(sealed_dir()?) || !path.is_file() {
+            return Err(format!(
+                "The encrypted copy {} no longer exists",
+                path.display()
+            ));
+        }
+        Ok(Self::track(path.to_path_buf()))
+    }
+
+    fn track(path: PathBuf) -> Self {
+        OPEN_SEALED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(path.clone());
+        Self { path }
+    }
+
+    /// Path of the encrypted copy
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SealedFile {
+    fn drop(&mut self) {
+        {
+            let mut open = OPEN_SEALED.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(i) = open.iter().position(|path| *path == self.path) {
+                open.swap_remove(i);
+            }
+            if open.contains(&self.path) {
+                return;
+            }
+        }
+        if !crate::cached_payments::is_payment_source(&self.path) {
+            remove_copy(&self.path);
+        }
+    }
+}
+
+/// Remove the encrypted copy once its cached payment is gone, unless an upload still uses it.
+/// Files out of the dir of the copies are never removed.
+pub fn remove_sealed(path: &Path) {
+    let in_use = OPEN_SEALED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .any(|open| open == path);
+    if !in_use && sealed_dir().is_ok_and(|dir| path.starts_with(dir)) {
+        remove_copy(path);
+    }
+}
+
+fn remove_copy(path: &Path) {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            error!("Error removing sealed file {}: {e}", path.display());
+        }
+        _ => {}
+    }
+}
+
+/// The copies are kept next to the cached payments, which may outlive the temp dir
+fn sealed_dir() -> Result<PathBuf, String> {
+    crate::cached_payments::get_client_data_dir_path()
+        .map(|dir| dir.join("sealed"))
+        .map_err(|e| format!("Error getting data dir: {e}"))
+}
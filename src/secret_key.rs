@@ -0,0 +1,39 @@
+//! Secret key of the wallet paying for uploads, kept in memory that is wiped once dropped.
+
+use zeroize::{Zeroize, Zeroizing};
+
+/// EVM hex encoded secret key, wiped from memory when dropped and never printed
+#[derive(Clone, Default)]
+pub struct SecretKey(Zeroizing<String>);
+
+impl SecretKey {
+    /// Take the key typed in the text buffer, wiping the buffer
+    pub fn take_from(buffer: &mut String) -> Self {
+        let key = Self::from(buffer.trim());
+        buffer.zeroize();
+        key
+    }
+
+    /// The key itself, to load the wallet from
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for SecretKey {
+    fn from(key: &str) -> Self {
+        Self(Zeroizing::new(key.to_string()))
+    }
+}
+
+impl From<String> for SecretKey {
+    fn from(key: String) -> Self {
+        Self(Zeroizing::new(key))
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey(***)")
+    }
+}
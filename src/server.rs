@@ -1,82 +1,807 @@
-use autonomi::{Bytes, Client, Wallet, client::payment::PaymentOption};
+use crate::backend::{AutonomiBackend, PutError, Quote, StorageBackend, WalletBalance};
+use crate::cached_payments::UploadSource;
+use crate::config::{Config, NetworkProfile};
+use crate::encryption::EncryptedFile;
+use crate::secret_key::SecretKey;
 
+use autonomi::client::ChunkBatchUploadState;
+use autonomi::client::payment::Receipt;
+use autonomi::files::{Metadata, PublicArchive};
+use autonomi::{AttoTokens, Bytes, Chunk, XorName, data::DataAddress};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+/// Built-in networks the client can connect to, others can be configured as [`NetworkProfile`]s
 pub const ENVIRONMENTS: [&str; 3] = ["local", "autonomi", "alpha"];
 pub const DEFAULT_ENVIRONMENT: &str = "alpha";
-pub const DEFAULT_LOCAL_SECRET_KEY: &str =
-    "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
 
+/// Length of a hex encoded data address, anything longer is taken for a hex data map
+const DATA_ADDRESS_HEX_LEN: usize = 64;
+
+/// Number of chunks read back from disk and stored at once, bounding the memory used by uploads
+const CHUNK_UPLOAD_BATCH_SIZE: usize = 8;
+
+/// Progress of a file upload, counted in chunks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadProgress {
+    /// Chunks the file was encrypted into, the data map chunk included
+    pub total: usize,
+    pub quoted: usize,
+    pub paid: usize,
+    pub stored: usize,
+}
+
+impl UploadProgress {
+    /// Fraction of the chunks stored so far
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.stored as f32 / self.total as f32
+    }
+}
+
+/// A file encrypted and quoted, waiting to be paid for and uploaded.
+/// Dropping it discards the encrypted chunks without paying anything.
+#[derive(Debug)]
+pub struct PreparedUpload {
+    file: EncryptedFile,
+    path: PathBuf,
+    filename: String,
+    receipt: Option<Receipt>,
+    /// Share of a payment made along with other uploads, see [`Server::pay_prepared`]
+    batch_price: Option<AttoTokens>,
+    /// Whether the data map is kept off the network
+    private: bool,
+    /// Whether the file is a copy encrypted with a passphrase
+    passphrase: bool,
+    quote: Quote,
+    progress: UploadProgress,
+}
+
+impl PreparedUpload {
+    /// Cost of uploading the file, zero when reusing a cached payment
+    pub fn quote(&self) -> Quote {
+        self.quote
+    }
+
+    /// Size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Hex encoded sha256 of the file content
+    pub fn content_hash(&self) -> &str {
+        self.file.content_hash()
+    }
+
+    /// Whether uploading spends tokens, false when reusing a cached payment or
+    /// when all the chunks are already stored on the network
+    pub fn needs_payment(&self) -> bool {
+        self.receipt.is_none() && self.quote.paid_chunks > 0
+    }
+
+    /// Whether all the chunks of the file are already stored on the network,
+    /// uploading it then only returns its address
+    pub fn is_stored(&self) -> bool {
+        self.receipt.is_none() && self.batch_price.is_none() && self.quote.paid_chunks == 0
+    }
+
+    /// Mark the file as a copy encrypted with a passphrase, so a payment cached for it keeps
+    /// the copy and retries it as such
+    pub fn set_passphrase_encrypted(&mut self) {
+        self.passphrase = true;
+    }
+
+    /// Where the file comes from, remembered along with its cached payment
+    fn source(&self) -> UploadSource {
+        UploadSource {
+            path: self.path.clone(),
+            private: self.private,
+            filename: Some(self.filename.clone()),
+            passphrase: self.passphrase,
+        }
+    }
+
+    /// Hex address of the file, or its hex data map if kept private
+    fn address(&self) -> String {
+        if self.private {
+            self.file.data_map()
+        } else {
+            self.file.address().to_hex()
+        }
+    }
+}
+
+/// File uploaded as part of a folder, to be listed in its archive
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    /// Path of the file within the archive
+    pub path: PathBuf,
+    /// Hex address of the uploaded file
+    pub address: String,
+    pub size: u64,
+    /// Creation and modification times in seconds since the unix epoch
+    pub created: u64,
+    pub modified: u64,
+}
+
+/// Longest wait between two retries, however many retries came before
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+/// How chunks failing to upload after being paid for are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Number of retries before giving up, 0 to never retry
+    pub retries: u32,
+    /// Delay before the first retry, doubled after every retry up to [`MAX_RETRY_DELAY`]
+    pub initial_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            initial_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Connection to the network along with the wallet paying for uploads
 #[derive(Clone)]
-pub struct Server {
-    wallet: Wallet,
-    client: Client,
+pub struct Server<B: StorageBackend = AutonomiBackend> {
+    backend: B,
+    retry_policy: RetryPolicy,
+    /// Held while paying from the wallet, concurrent transactions would race on its nonce
+    payments: Arc<Mutex<()>>,
 }
 
 impl Server {
-    pub async fn new(mut secret_key: &str, environment: &str) -> Result<Self, String> {
-        println!("Initializing client with environment: {environment:?}");
+    /// Connect to the given environment and load the wallet from the EVM hex encoded secret key
+    pub async fn new(secret_key: &str, environment: &str) -> Result<Self, String> {
+        Self::init(secret_key, environment).await
+    }
+
+    /// Connect to a user-defined network and load the wallet from the EVM hex encoded secret key
+    pub async fn new_with_network(
+        secret_key: &str,
+        network: &NetworkProfile,
+    ) -> Result<Self, String> {
+        let backend = AutonomiBackend::init_with_network(secret_key, network).await?;
+        Ok(Self::with_backend(backend))
+    }
 
-        let client = init_client(environment).await?;
-        println!("Client initialized");
+    /// Connect to the configured network with the name, or else to the built-in environment.
+    /// The key is wiped from memory once the wallet is loaded.
+    pub async fn connect(
+        secret_key: SecretKey,
+        environment: &str,
+        config: &Config,
+    ) -> Result<Self, String> {
+        let secret_key = secret_key.expose();
+        match config.network(environment) {
+            Some(network) => Self::new_with_network(secret_key, network).await,
+            None => Self::new(secret_key, environment).await,
+        }
+    }
+}
 
-        let evm_network = client.evm_network();
-        println!("EVM network: {evm_network:?}");
+impl<B: StorageBackend> Server<B> {
+    /// Connect to the given environment using the backend `B`
+    pub async fn init(secret_key: &str, environment: &str) -> Result<Self, String> {
+        let backend = B::init(secret_key, environment).await?;
+        Ok(Self::with_backend(backend))
+    }
 
-        if environment == "local" && secret_key.is_empty() {
-            secret_key = DEFAULT_LOCAL_SECRET_KEY;
+    pub fn with_backend(backend: B) -> Self {
+        Self {
+            backend,
+            retry_policy: RetryPolicy::default(),
+            payments: Arc::new(Mutex::new(())),
         }
-        let wallet =
-            Wallet::new_from_private_key(evm_network.clone(), secret_key).map_err(|e| {
-                println!("Error loading wallet: {e}");
-                format!("Error loading wallet: {e}")
-            })?;
-        println!("Wallet loaded");
+    }
 
-        Ok(Self { wallet, client })
+    /// Retry failed chunks with this policy instead of the default one
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
     }
 
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Upload public data, returns its hex address and the cost paid.
+    /// A payment cached by a previous failed attempt for the same data is reused.
     pub async fn put_data(&self, bytes: &[u8], filename: &str) -> Result<(String, String), String> {
-        println!("Uploading {} bytes...", bytes.len());
+        info!("Uploading {} bytes...", bytes.len());
 
         // use existing payment if available (from previous failed attempt)
-        let wallet = self.wallet.clone();
-        let payment =
-            if let Ok(Some(receipt)) = crate::cached_payments::load_payment_for_file(filename) {
-                println!("Using cached payment: no need to re-pay");
-                PaymentOption::Receipt(receipt)
-            } else {
-                PaymentOption::Wallet(wallet)
-            };
-
-        // upload data
+        let content_hash = sha256::digest(bytes);
         let bytes = Bytes::from(bytes.to_vec());
-        let (price, addr) = match self.client.data_put_public(bytes, payment).await {
+        let mut receipt = match data_chunks(&bytes) {
+            Some(chunks) => self.cached_receipt(&content_hash, filename, &chunks).await,
+            None => None,
+        };
+
+        // upload data, retrying with the payment already made if some chunks failed
+        let mut delay = self.retry_policy.initial_delay.min(MAX_RETRY_DELAY);
+        let mut attempt = 0;
+        let res = loop {
+            let put = {
+                // without a receipt the data is paid for from the wallet, one payment at a time
+                let _paying = match receipt {
+                    None => Some(self.payments.lock().await),
+                    Some(_) => None,
+                };
+                self.backend.put(bytes.clone(), receipt.clone()).await
+            };
+            match put {
+                Err(PutError::Batch(upload_state))
+                    if attempt < self.retry_policy.retries && upload_state.payment.is_some() =>
+                {
+                    attempt += 1;
+                    warn!(
+                        "Error uploading data: {upload_state}, retrying in {delay:?} ({attempt}/{})",
+                        self.retry_policy.retries
+                    );
+                    receipt = upload_state.payment;
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2).min(MAX_RETRY_DELAY);
+                }
+                res => break res,
+            }
+        };
+        let (price, addr) = match res {
             Ok((price, addr)) => (price, addr),
             // save payment to local disk for re-use if upload failed
-            Err(autonomi::client::PutError::Batch(upload_state)) => {
-                let res = crate::cached_payments::save_payment(filename, &upload_state);
-                println!("Error uploading data: {upload_state}");
-                println!("Cached payment to local disk for retry: {filename}: {res:?}");
+            Err(PutError::Batch(upload_state)) => {
+                let res = crate::cached_payments::save_payment_for_content(
+                    &content_hash,
+                    None,
+                    &upload_state,
+                );
+                error!("Error uploading data: {upload_state}");
+                info!("Cached payment to local disk for retry: {filename}: {res:?}");
                 return Err(format!("Error uploading data: {upload_state}"));
             }
             Err(e) => {
-                println!("Error uploading data: {e}");
+                error!("Error uploading data: {e}");
                 return Err(format!("Error uploading data: {e}"));
             }
         };
 
-        println!("Upload complete with price: {price:?} at: {addr:?}");
+        info!("Upload complete with price: {price:?} at: {addr:?}");
+        Ok((addr.to_hex(), price.to_string()))
+    }
+
+    /// Same as [`Server::put_data`], encrypting the data with the passphrase first.
+    /// Download it back with [`Server::get_data_with_passphrase`].
+    pub async fn put_data_with_passphrase(
+        &self,
+        bytes: &[u8],
+        filename: &str,
+        passphrase: &str,
+    ) -> Result<(String, String), String> {
+        let sealed = crate::passphrase::encrypt(bytes, passphrase).map_err(|e| {
+            error!("{e}");
+            e
+        })?;
+        self.put_data(&sealed, filename).await
+    }
+
+    /// Upload a file as public data without reading it all in memory,
+    /// returns its hex address and the cost paid.
+    /// A payment cached by a previous failed attempt for the same content is reused.
+    pub async fn put_file(&self, path: &Path, filename: &str) -> Result<(String, String), String> {
+        self.put_file_with_progress(path, filename, |_| {}).await
+    }
+
+    /// Upload a file keeping its data map private, returns the hex data map and the cost paid.
+    /// Only those given the data map can download the file.
+    pub async fn put_private_file(
+        &self,
+        path: &Path,
+        filename: &str,
+    ) -> Result<(String, String), String> {
+        let prepared = self.prepare_private_file(path, filename, &|_| {}).await?;
+        self.upload_prepared(prepared, &|_| {}).await
+    }
+
+    /// Same as [`Server::put_file`], reporting progress as chunks are quoted, paid for and stored
+    pub async fn put_file_with_progress(
+        &self,
+        path: &Path,
+        filename: &str,
+        progress: impl Fn(UploadProgress) + Send + Sync,
+    ) -> Result<(String, String), String> {
+        let prepared = self.prepare_file(path, filename, &progress).await?;
+        self.upload_prepared(prepared, &progress).await
+    }
+
+    /// Encrypt the file and get the cost of storing its chunks, without paying anything yet.
+    /// Pass the result to [`Server::upload_prepared`] to pay and upload.
+    pub async fn prepare_file(
+        &self,
+        path: &Path,
+        filename: &str,
+        progress: &(impl Fn(UploadProgress) + Sync),
+    ) -> Result<PreparedUpload, String> {
+        self.prepare(path, filename, false, progress).await
+    }
+
+    /// Same as [`Server::prepare_file`], keeping the data map of the file private.
+    /// Once uploaded, the file can only be downloaded with the hex data map returned.
+    pub async fn prepare_private_file(
+        &self,
+        path: &Path,
+        filename: &str,
+        progress: &(impl Fn(UploadProgress) + Sync),
+    ) -> Result<PreparedUpload, String> {
+        self.prepare(path, filename, true, progress).await
+    }
+
+    async fn prepare(
+        &self,
+        path: &Path,
+        filename: &str,
+        private: bool,
+        progress: &(impl Fn(UploadProgress) + Sync),
+    ) -> Result<PreparedUpload, String> {
+        info!("Encrypting {}...", path.display());
+        let mut file = EncryptedFile::encrypt(path).await.map_err(|e| {
+            error!("{e}");
+            e
+        })?;
+        if private {
+            file.keep_data_map_private();
+        }
+        let chunks = file.chunks();
+        let mut state = UploadProgress {
+            total: chunks.len(),
+            ..Default::default()
+        };
+        progress(state);
+
+        // use existing payment if available (from previous failed attempt)
+        let receipt = self
+            .cached_receipt(file.content_hash(), filename, chunks)
+            .await;
+
+        let quote = match receipt {
+            Some(_) => Quote::default(),
+            None => self.backend.quote_chunks(chunks).await.map_err(|e| {
+                error!("Error getting quote: {e}");
+                format!("Error getting quote: {e}")
+            })?,
+        };
+        info!("Quoted {} chunks for {quote:?}", chunks.len());
+        state.quoted = state.total;
+        progress(state);
+
+        Ok(PreparedUpload {
+            file,
+            path: path.to_path_buf(),
+            filename: filename.to_string(),
+            receipt,
+            batch_price: None,
+            private,
+            passphrase: false,
+            quote,
+            progress: state,
+        })
+    }
+
+    /// Pay for all the prepared uploads needing it in a single payment, sparing the gas of
+    /// paying for them one by one, unless their price rose above their quotes altogether.
+    /// Returns the amount paid, the uploads then use the payment when passed to
+    /// [`Server::upload_prepared`].
+    pub async fn pay_prepared(&self, prepared: &mut [PreparedUpload]) -> Result<String, String> {
+        let mut unpaid: Vec<_> = prepared
+            .iter_mut()
+            .filter(|prepared| prepared.needs_payment())
+            .collect();
+        if unpaid.is_empty() {
+            return Ok(AttoTokens::zero().to_string());
+        }
+        let chunks: Vec<_> = unpaid
+            .iter()
+            .flat_map(|prepared| prepared.file.chunks().iter().copied())
+            .collect();
+        info!(
+            "Paying for {} chunks of {} files...",
+            chunks.len(),
+            unpaid.len()
+        );
+
+        // every upload gets the part of the receipt for its chunks, cached by the payment task
+        let files: Vec<_> = unpaid
+            .iter()
+            .map(|prepared| PaidFile {
+                chunks: prepared
+                    .file
+                    .chunks()
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .collect(),
+                content_hash: prepared.file.content_hash().to_string(),
+                source: prepared.source(),
+            })
+            .collect();
+        let quoted = unpaid.iter().fold(AttoTokens::zero(), |total, prepared| {
+            total.checked_add(prepared.quote.tokens).unwrap_or(total)
+        });
+        let (receipt, price) = self
+            .pay_and_cache(chunks, None, quoted, files.clone())
+            .await?;
+
+        // and its share of the price
+        for (prepared, file) in unpaid.iter_mut().zip(&files) {
+            prepared.receipt = Some(file.receipt(&receipt));
+            prepared.batch_price = Some(price_share(price, prepared.quote.tokens, quoted));
+        }
+        Ok(price.to_string())
+    }
+
+    /// Pay for and upload a prepared file, returns its hex address and the cost paid.
+    /// Nothing is paid nor uploaded when the file is already stored on the network, nor when
+    /// its price rose above the quote since it was prepared.
+    pub async fn upload_prepared(
+        &self,
+        prepared: PreparedUpload,
+        progress: &(impl Fn(UploadProgress) + Sync),
+    ) -> Result<(String, String), String> {
+        if prepared.is_stored() {
+            let address = prepared.address();
+            info!("{} is already on the network", prepared.filename);
+            progress(UploadProgress {
+                paid: prepared.progress.total,
+                stored: prepared.progress.total,
+                ..prepared.progress
+            });
+            return Ok((address, AttoTokens::zero().to_string()));
+        }
+
+        let source = prepared.source();
+        let PreparedUpload {
+            file,
+            filename,
+            receipt: cached_receipt,
+            batch_price,
+            quote,
+            progress: mut state,
+            ..
+        } = prepared;
+        let chunks = file.chunks();
+        info!("Uploading {} chunks...", chunks.len());
+
+        let (receipt, price) = match (cached_receipt, batch_price) {
+            // already paid for along with other uploads
+            (Some(receipt), Some(price)) => (receipt, price),
+            // keep the payment until all chunks are stored, so a cancelled upload can reuse it
+            (cached_receipt, _) => {
+                let paid_file = PaidFile {
+                    chunks: chunks.iter().map(|(name, _)| *name).collect(),
+                    content_hash: file.content_hash().to_string(),
+                    source: source.clone(),
+                };
+                let files = vec![paid_file];
+                self.pay_and_cache(chunks.to_vec(), cached_receipt, quote.tokens, files)
+                    .await?
+            }
+        };
+        state.paid = state.total;
+        progress(state);
+
+        // upload chunks a batch at a time, reading them back from disk
+        for batch in chunks.chunks(CHUNK_UPLOAD_BATCH_SIZE) {
+            let batch_len = batch.len();
+            let batch = batch
+                .iter()
+                .map(|(name, _)| file.read_chunk(name))
+                .collect::<Result<Vec<_>, _>>();
+            let res = match batch {
+                Ok(batch) => self.put_chunks_with_retries(batch, &receipt).await,
+                Err(e) => Err(PutError::Other(e)),
+            };
+
+            // save payment to local disk for re-use if upload failed
+            if let Err(e) = res {
+                let error = format!("Error uploading data: {e}");
+                let mut upload_state = match e {
+                    PutError::Batch(upload_state) => upload_state,
+                    PutError::Other(_) => ChunkBatchUploadState::default(),
+                };
+                upload_state.payment.get_or_insert_with(|| receipt.clone());
+                let res = crate::cached_payments::save_payment_for_content(
+                    file.content_hash(),
+                    Some(&source),
+                    &upload_state,
+                );
+                error!("{error}");
+                info!("Cached payment to local disk for retry: {filename}: {res:?}");
+                return Err(error);
+            }
+            state.stored += batch_len;
+            progress(state);
+        }
+
+        if let Err(e) = crate::cached_payments::remove_payment_for_content(file.content_hash()) {
+            error!("Error removing cached payment for {filename}: {e}");
+        }
+
+        // a private file is found with its data map rather than the address of the data map chunk
+        let addr = file.address();
+        info!("Upload complete with price: {price:?} at: {addr:?}");
+        if source.private {
+            return Ok((file.data_map(), price.to_string()));
+        }
         Ok((addr.to_hex(), price.to_string()))
     }
+
+    /// Payment cached by a previous failed attempt for the content, or under the file name by the
+    /// ant CLI, if it pays for every chunk still needing payment and for nothing else
+    async fn cached_receipt(
+        &self,
+        content_hash: &str,
+        filename: &str,
+        chunks: &[(XorName, usize)],
+    ) -> Option<Receipt> {
+        let receipt =
+            crate::cached_payments::load_payment_for_content(content_hash, filename).ok()??;
+        let names: HashSet<_> = chunks.iter().map(|(name, _)| name).collect();
+        if receipt.is_empty() || !receipt.keys().all(|name| names.contains(name)) {
+            warn!("Ignoring cached payment for {filename}: it paid for other content");
+            return None;
+        }
+
+        // chunks left out of the payment must be stored on the network already
+        let unpaid: Vec<_> = chunks
+            .iter()
+            .filter(|(name, _)| !receipt.contains_key(name))
+            .copied()
+            .collect();
+        if !unpaid.is_empty() {
+            let free = self.backend.quote_chunks(&unpaid).await;
+            if !free.is_ok_and(|quote| quote.paid_chunks == 0) {
+                warn!(
+                    "Ignoring cached payment for {filename}: {} of its chunks are not paid for",
+                    unpaid.len()
+                );
+                return None;
+            }
+        }
+        info!("Using cached payment: no need to re-pay");
+        Some(receipt)
+    }
+
+    /// Pay for the chunks from the wallet, or with the receipt of a previous payment, and cache
+    /// the part of the payment for each file. A wallet payment costing more than the `approved`
+    /// quote is refused. Runs as a task of its own that goes on when the upload is aborted, so a
+    /// payment made is always cached.
+    async fn pay_and_cache(
+        &self,
+        chunks: Vec<(XorName, usize)>,
+        receipt: Option<Receipt>,
+        approved: AttoTokens,
+        files: Vec<PaidFile>,
+    ) -> Result<(Receipt, AttoTokens), String> {
+        let server = self.clone();
+        let payment = async move {
+            // without a receipt the chunks are paid for from the wallet, one payment at a time
+            let paying = match receipt {
+                None => Some(server.payments.lock().await),
+                Some(_) => None,
+            };
+            let paid = server.backend.pay(&chunks, receipt, approved).await;
+            let (receipt, price) = paid.map_err(|e| {
+                error!("Error paying for upload: {e}");
+                format!("Error paying for upload: {e}")
+            })?;
+            drop(paying);
+            for file in &files {
+                cache_payment(file, &file.receipt(&receipt));
+            }
+            Ok((receipt, price))
+        };
+        tokio::spawn(payment)
+            .await
+            .map_err(|e| format!("Payment task failed: {e}"))?
+    }
+
+    /// Store the chunks, retrying the ones that failed with exponential backoff
+    async fn put_chunks_with_retries(
+        &self,
+        mut chunks: Vec<Chunk>,
+        receipt: &Receipt,
+    ) -> Result<(), PutError> {
+        let mut delay = self.retry_policy.initial_delay.min(MAX_RETRY_DELAY);
+        let mut attempt = 0;
+        loop {
+            match self.backend.put_chunks(chunks.clone(), receipt).await {
+                Err(PutError::Batch(upload_state)) if attempt < self.retry_policy.retries => {
+                    // only retry the failed chunks, if the backend told which ones failed
+                    let failed: HashSet<_> = upload_state
+                        .failed
+                        .iter()
+                        .map(|(address, _)| *address.xorname())
+                        .collect();
+                    if !failed.is_empty() {
+                        chunks.retain(|chunk| failed.contains(chunk.name()));
+                    }
+
+                    attempt += 1;
+                    warn!(
+                        "{} chunks failed to upload, retrying in {delay:?} ({attempt}/{})",
+                        chunks.len(),
+                        self.retry_policy.retries
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2).min(MAX_RETRY_DELAY);
+                }
+                res => return res,
+            }
+        }
+    }
+
+    /// Upload a public archive listing the already uploaded files, returns its hex address and the cost paid.
+    /// Sharing the archive address shares all the files at once.
+    pub async fn put_archive(
+        &self,
+        entries: &[ArchiveEntry],
+        name: &str,
+    ) -> Result<(String, String), String> {
+        info!("Creating archive {name} of {} files...", entries.len());
+
+        let mut archive = PublicArchive::new();
+        for entry in entries {
+            let addr = DataAddress::from_hex(&entry.address).map_err(|e| {
+                error!("Invalid address for {}: {e}", entry.path.display());
+                format!("Invalid address for {}: {e}", entry.path.display())
+            })?;
+            let metadata = Metadata {
+                created: entry.created,
+                modified: entry.modified,
+                size: entry.size,
+                extra: None,
+            };
+            archive.add_file(entry.path.clone(), addr, metadata);
+        }
+
+        let bytes = archive.to_bytes().map_err(|e| {
+            error!("Error serializing archive: {e}");
+            format!("Error serializing archive: {e}")
+        })?;
+        self.put_data(&bytes, name).await
+    }
+
+    /// Download public data from its hex address, or private data from its hex data map
+    pub async fn get_data(&self, address: &str) -> Result<Vec<u8>, String> {
+        if address.trim().len() > DATA_ADDRESS_HEX_LEN {
+            return self.get_private_data(address).await;
+        }
+        info!("Downloading data at {address}...");
+
+        let addr = DataAddress::from_hex(address.trim()).map_err(|e| {
+            info!("Invalid address {address:?}: {e}");
+            format!("Invalid address: {e}")
+        })?;
+
+        let bytes = self.backend.get(&addr).await.map_err(|e| {
+            error!("Error downloading data: {e}");
+            format!("Error downloading data: {e}")
+        })?;
+
+        info!("Download complete: {} bytes from: {addr:?}", bytes.len());
+        Ok(bytes.to_vec())
+    }
+
+    /// Same as [`Server::get_data`], decrypting data uploaded with a passphrase
+    pub async fn get_data_with_passphrase(
+        &self,
+        address: &str,
+        passphrase: &str,
+    ) -> Result<Vec<u8>, String> {
+        let bytes = self.get_data(address).await?;
+        crate::passphrase::decrypt(&bytes, passphrase).map_err(|e| {
+            error!("Error decrypting data: {e}");
+            e
+        })
+    }
+
+    async fn get_private_data(&self, data_map: &str) -> Result<Vec<u8>, String> {
+        info!("Downloading private data...");
+        let data_map_chunk = crate::encryption::data_map_chunk_from_hex(data_map)?;
+        let bytes = self
+            .backend
+            .get_private(&data_map_chunk)
+            .await
+            .map_err(|e| {
+                error!("Error downloading data: {e}");
+                format!("Error downloading data: {e}")
+            })?;
+
+        info!("Download complete: {} bytes", bytes.len());
+        Ok(bytes.to_vec())
+    }
+
+    /// Address and balances of the wallet paying for uploads
+    pub async fn balance(&self) -> Result<WalletBalance, String> {
+        self.backend.balance().await.map_err(|e| {
+            error!("Error getting wallet balance: {e}");
+            e
+        })
+    }
+
+    /// Get the cost of uploading the data
+    pub async fn quote_data(&self, bytes: &[u8]) -> Result<String, String> {
+        let bytes = Bytes::from(bytes.to_vec());
+        let price = self.backend.quote(bytes).await.map_err(|e| {
+            error!("Error getting quote: {e}");
+            format!("Error getting quote: {e}")
+        })?;
+        Ok(price.to_string())
+    }
+}
+
+/// Names and sizes of the chunks the data is self-encrypted into, its data map chunk included
+fn data_chunks(bytes: &Bytes) -> Option<Vec<(XorName, usize)>> {
+    let (data_map_chunk, chunks) = autonomi::self_encryption::encrypt(bytes.clone()).ok()?;
+    let chunks = chunks.iter().chain([&data_map_chunk]);
+    Some(chunks.map(|chunk| (*chunk.name(), chunk.size())).collect())
 }
 
-async fn init_client(environment: &str) -> Result<Client, String> {
-    let res = match environment {
-        "local" => Client::init_local().await,
-        "alpha" => Client::init_alpha().await,
-        _ => Client::init().await, // "autonomi"
+/// File paid for, to cache its part of the payment
+#[derive(Debug, Clone)]
+struct PaidFile {
+    chunks: HashSet<XorName>,
+    content_hash: String,
+    source: UploadSource,
+}
+
+impl PaidFile {
+    /// Part of the receipt paying for the chunks of the file
+    fn receipt(&self, receipt: &Receipt) -> Receipt {
+        receipt
+            .iter()
+            .filter(|(name, _)| self.chunks.contains(*name))
+            .map(|(name, payment)| (*name, payment.clone()))
+            .collect()
+    }
+}
+
+/// Cache the payment for the file until all its chunks are stored
+fn cache_payment(file: &PaidFile, receipt: &Receipt) {
+    let paid_state = ChunkBatchUploadState {
+        payment: Some(receipt.clone()),
+        ..Default::default()
     };
-    res.map_err(|e| {
-        println!("Error initializing client: {e}");
-        format!("Error initializing client: {e}")
-    })
+    let res = crate::cached_payments::save_payment_for_content(
+        &file.content_hash,
+        Some(&file.source),
+        &paid_state,
+    );
+    if let Err(e) = res {
+        error!(
+            "Error caching payment for {}: {e}",
+            file.source.path.display()
+        );
+    }
+}
+
+/// Part of the price paid for the upload quoted `quote` out of uploads quoted `total` altogether
+fn price_share(price: AttoTokens, quote: AttoTokens, total: AttoTokens) -> AttoTokens {
+    let atto = |tokens: AttoTokens| u128::try_from(tokens.as_atto()).unwrap_or(u128::MAX);
+    if atto(total) == 0 {
+        return AttoTokens::zero();
+    }
+    let share = atto(price)
+        .checked_mul(atto(quote))
+        .map(|paid| paid / atto(total))
+        .unwrap_or_else(|| (atto(price) / atto(total)).saturating_mul(atto(quote)));
+    AttoTokens::from_atto(share.into())
 }
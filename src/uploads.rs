@@ -0,0 +1,1366 @@
+//! Upload rows shown by the app and the events their background tasks report.
+
+use crate::Server;
+use crate::backend::{Quote, StorageBackend};
+use crate::cached_payments::CachedPayment;
+use crate::history::HistoryEntry;
+use crate::passphrase::SealedFile;
+use crate::server::{ArchiveEntry, PreparedUpload, UploadProgress};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::AbortHandle;
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+/// Number of uploads running at once unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT: usize = 4;
+
+/// Where an upload is in the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadState {
+    /// Waiting for a free slot to start
+    Queued,
+    Running,
+    /// Stopped until resumed, the payment made if any is kept
+    Paused,
+    /// Finished, successfully or not
+    Done,
+}
+
+#[derive(Clone)]
+pub struct UploadStatus {
+    pub filename: String,
+    /// When the upload started running
+    pub start_time: std::time::Instant,
+    pub state: UploadState,
+    pub success: Option<bool>,
+    pub message: String,
+    pub time_to_complete: Option<f32>, // Store completion time when finished
+    /// Id of the folder or of the files dropped together the row belongs to, if any.
+    /// The row leading the group is named after it.
+    pub group: Option<usize>,
+    /// Whether this row is the archive listing all the files of its folder
+    pub is_archive: bool,
+    /// Whether this row pays for all the files dropped together in one payment,
+    /// each file being uploaded on its own afterwards
+    pub is_batch: bool,
+    /// Chunks quoted, paid for and stored so far, once the file is encrypted
+    pub progress: Option<UploadProgress>,
+    /// When the first chunks started being stored, to estimate the time left
+    pub storing_since: Option<Instant>,
+    /// Cost of the upload once quoted, for a folder archive the cost of all its files
+    pub quote: Option<Quote>,
+    /// Whether the upload waits for the payment to be approved
+    pub awaiting_approval: bool,
+    /// Gas saved in wei by paying for the files of the group at once rather than one by one
+    pub gas_saved: Option<u128>,
+    /// Size and hex encoded sha256 of the file, once encrypted
+    pub size: Option<u64>,
+    pub content_hash: Option<String>,
+    /// Whether all the chunks of the file were already on the network, so nothing was paid
+    pub already_stored: bool,
+    /// Cost paid for a successful upload
+    pub cost: Option<String>,
+    /// Hex address of a successful upload, or its hex data map if kept private.
+    /// Files paid for together have none, their files being uploaded on their own.
+    pub address: Option<String>,
+    /// File uploaded, or folder for an archive row, to retry the upload from
+    pub source: Option<PathBuf>,
+    /// Whether the data map is kept private, the address of the upload then being its data map
+    pub private: bool,
+    /// Passphrase the file is encrypted with before being uploaded, if any, wiped once dropped
+    pub passphrase: Option<Zeroizing<String>>,
+    /// Copy of the file encrypted with the passphrase, kept so retries upload the same bytes
+    pub sealed: Option<Arc<SealedFile>>,
+    /// Seconds since the unix epoch the upload completed at, for rows loaded from the history
+    pub uploaded_at: Option<u64>,
+}
+
+impl std::fmt::Debug for UploadStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UploadStatus")
+            .field("filename", &self.filename)
+            .field("start_time", &self.start_time)
+            .field("state", &self.state)
+            .field("success", &self.success)
+            .field("message", &self.message)
+            .field("time_to_complete", &self.time_to_complete)
+            .field("group", &self.group)
+            .field("is_archive", &self.is_archive)
+            .field("is_batch", &self.is_batch)
+            .field("progress", &self.progress)
+            .field("storing_since", &self.storing_since)
+            .field("quote", &self.quote)
+            .field("awaiting_approval", &self.awaiting_approval)
+            .field("gas_saved", &self.gas_saved)
+            .field("size", &self.size)
+            .field("content_hash", &self.content_hash)
+            .field("already_stored", &self.already_stored)
+            .field("cost", &self.cost)
+            .field("address", &self.address)
+            .field("source", &self.source)
+            .field("private", &self.private)
+            // the passphrase itself is never printed
+            .field("passphrase", &self.passphrase.as_ref().map(|_| "***"))
+            .field("sealed", &self.sealed)
+            .field("uploaded_at", &self.uploaded_at)
+            .finish()
+    }
+}
+
+impl UploadStatus {
+    pub fn new(filename: &str) -> Self {
+        Self {
+            filename: filename.to_string(),
+            start_time: std::time::Instant::now(),
+            state: UploadState::Queued,
+            success: None,
+            message: format!("Queued {filename}"),
+            time_to_complete: None,
+            group: None,
+            is_archive: false,
+            is_batch: false,
+            progress: None,
+            storing_since: None,
+            quote: None,
+            awaiting_approval: false,
+            gas_saved: None,
+            size: None,
+            content_hash: None,
+            already_stored: false,
+            cost: None,
+            address: None,
+            source: None,
+            private: false,
+            passphrase: None,
+            sealed: None,
+            uploaded_at: None,
+        }
+    }
+
+    /// Completed row of an upload recorded in the history, folder archives having no content hash
+    pub fn from_history(entry: &HistoryEntry) -> Self {
+        Self {
+            state: UploadState::Done,
+            success: Some(true),
+            message: format!(
+                "{} was uploaded. At address: {}",
+                entry.filename, entry.address
+            ),
+            is_archive: entry.content_hash.is_empty(),
+            size: Some(entry.size),
+            content_hash: Some(entry.content_hash.clone()).filter(|hash| !hash.is_empty()),
+            cost: Some(entry.cost.clone()),
+            address: Some(entry.address.clone()),
+            private: entry.private,
+            uploaded_at: Some(entry.timestamp),
+            ..Self::new(&entry.filename)
+        }
+    }
+
+    /// Whether the row uploads the files of its group, as a folder archive or a batch of files
+    pub fn leads_group(&self) -> bool {
+        self.is_archive || self.is_batch
+    }
+
+    /// Whether the upload finished, successfully or not
+    pub fn is_done(&self) -> bool {
+        self.state == UploadState::Done
+    }
+
+    /// Reset the row to queued, keeping where it belongs
+    fn restart(&mut self) {
+        *self = Self {
+            group: self.group.take(),
+            is_archive: self.is_archive,
+            is_batch: self.is_batch,
+            source: self.source.take(),
+            private: self.private,
+            passphrase: self.passphrase.take(),
+            sealed: self.sealed.take(),
+            ..Self::new(&self.filename)
+        };
+    }
+
+    /// Mark the row as running from now
+    fn run(&mut self) {
+        self.state = UploadState::Running;
+        self.start_time = Instant::now();
+        self.message = if self.is_archive {
+            format!("Waiting for the files of {}...", self.filename)
+        } else if self.is_batch {
+            format!("Paying for {}...", self.filename)
+        } else {
+            format!("Uploading {}...", self.filename)
+        };
+    }
+
+    /// Estimated time left to store the remaining chunks, at the rate they were stored so far
+    pub fn eta(&self) -> Option<Duration> {
+        let progress = self.progress?;
+        let storing_since = self.storing_since?;
+        if progress.stored == 0 || progress.stored >= progress.total {
+            return None;
+        }
+        let remaining = (progress.total - progress.stored) as f64 / progress.stored as f64;
+        Some(storing_since.elapsed().mul_f64(remaining))
+    }
+
+    /// Address of a successful upload
+    pub fn address(&self) -> Option<&str> {
+        if self.success != Some(true) {
+            return None;
+        }
+        self.address.as_deref()
+    }
+}
+
+// Define the status update event
+pub enum UploadEvent {
+    Complete {
+        index: usize,
+        address: String,
+        cost: String,
+        duration: std::time::Duration,
+        filename: String,
+    },
+    Failed {
+        index: usize,
+        filename: String,
+        duration: std::time::Duration,
+        error: String,
+    },
+    Progress {
+        index: usize,
+        progress: UploadProgress,
+    },
+    /// The file is encrypted, its size and content hash are known,
+    /// and whether it is already stored on the network
+    Prepared {
+        index: usize,
+        size: u64,
+        content_hash: String,
+        already_stored: bool,
+    },
+    /// The file is encrypted with its passphrase into a copy to upload instead
+    Sealed {
+        index: usize,
+        sealed: Arc<SealedFile>,
+    },
+    /// The upload is quoted and waits for the payment to be approved or rejected
+    Quoted {
+        index: usize,
+        quote: Quote,
+        /// Gas saved by paying for several files at once, in wei
+        gas_saved: u128,
+        approval: oneshot::Sender<bool>,
+    },
+    /// The files of the folder are listed, rows are to be added for them and their indices sent
+    Listed {
+        index: usize,
+        /// Path of every file and its path within the archive
+        files: Vec<(PathBuf, PathBuf)>,
+        rows: oneshot::Sender<Vec<usize>>,
+    },
+    /// The file is paid for along with the other files dropped with it,
+    /// its own upload waits for a free slot
+    Paid { index: usize, upload: UploadTask },
+}
+
+/// File of a folder upload, along with its row
+struct FolderFile {
+    index: usize,
+    path: PathBuf,
+    /// Path of the file within the archive
+    archive_path: PathBuf,
+    /// Address of the file if already uploaded
+    address: Option<String>,
+    /// Copy of the file encrypted with the passphrase by a previous attempt
+    sealed: Option<Arc<SealedFile>>,
+}
+
+type UploadTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Upload waiting for a free slot
+struct QueuedUpload {
+    /// Row reporting the end of the upload, the archive row for a folder
+    index: usize,
+    task: UploadTask,
+}
+
+/// Upload rows along with the channel their upload tasks report back on
+pub struct Uploads {
+    pub statuses: Vec<UploadStatus>, // Track multiple uploads
+    /// Uploads costing less than this many ANT are paid for without asking, all are asked if unset
+    pub auto_approve_below: Option<f64>,
+    /// Uploads costing more than this many ANT are refused without asking
+    pub max_upload_cost: Option<f64>,
+    /// Number of uploads running at once, a folder counting as one upload
+    pub max_concurrent: usize,
+    /// Whether files uploaded from now on keep their data map private
+    pub private: bool,
+    /// Passphrase files uploaded from now on are encrypted with, if any
+    pub passphrase: Option<Zeroizing<String>>,
+    queue: VecDeque<QueuedUpload>,
+    /// Rows of the uploads running, with the handle to stop them
+    running: BTreeMap<usize, AbortHandle>,
+    /// Id given to the next group of rows, so folders with the same name are told apart
+    next_group: usize,
+    approvals: BTreeMap<usize, oneshot::Sender<bool>>,
+    receiver: mpsc::UnboundedReceiver<UploadEvent>,
+    sender: mpsc::UnboundedSender<UploadEvent>,
+}
+
+impl Default for Uploads {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            statuses: Vec::new(),
+            auto_approve_below: None,
+            max_upload_cost: None,
+            max_concurrent: DEFAULT_MAX_CONCURRENT,
+            private: false,
+            passphrase: None,
+            queue: VecDeque::new(),
+            running: BTreeMap::new(),
+            next_group: 0,
+            approvals: BTreeMap::new(),
+            receiver,
+            sender,
+        }
+    }
+}
+
+impl Uploads {
+    /// Whether any upload is queued or running, paused ones are left aside
+    pub fn has_active(&self) -> bool {
+        self.statuses
+            .iter()
+            .any(|status| matches!(status.state, UploadState::Queued | UploadState::Running))
+    }
+
+    /// Add a row for the file and queue its upload, returns the row index
+    pub fn start<B: StorageBackend>(&mut self, server: &Server<B>, path: &Path) -> usize {
+        self.start_with(server, path, self.private, self.passphrase.clone())
+    }
+
+    /// Same as [`Uploads::start`], keeping the data map private and encrypting the file with the
+    /// passphrase as given rather than as set for new uploads
+    pub fn start_with<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        path: &Path,
+        private: bool,
+        passphrase: Option<Zeroizing<String>>,
+    ) -> usize {
+        let filename = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+        let mut status = UploadStatus::new(&filename);
+        status.source = Some(path.to_path_buf());
+        status.private = private;
+        status.passphrase = passphrase;
+        self.statuses.push(status);
+
+        let status_index = self.statuses.len() - 1;
+        self.queue_file(server, status_index, path.to_path_buf(), filename);
+        status_index
+    }
+
+    /// Add a row uploading the file a cached payment was made for again, in the mode the payment
+    /// was made for. A file encrypted with a passphrase is uploaded from the encrypted copy kept
+    /// with the payment, under the name of the original file.
+    pub fn retry_payment<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        payment: &CachedPayment,
+    ) -> Result<usize, String> {
+        let Some(source) = &payment.source else {
+            return Err("The file the payment was made for is unknown".to_string());
+        };
+        let sealed = if payment.passphrase {
+            Some(Arc::new(SealedFile::open(source)?))
+        } else {
+            None
+        };
+        let filename = payment.filename.clone().unwrap_or_else(|| {
+            source
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("unknown")
+                .to_string()
+        });
+        let mut status = UploadStatus::new(&filename);
+        status.source = Some(source.clone());
+        status.private = payment.private;
+        status.sealed = sealed;
+        self.statuses.push(status);
+
+        let status_index = self.statuses.len() - 1;
+        self.queue_file(server, status_index, source.clone(), filename);
+        Ok(status_index)
+    }
+
+    /// Add a row for the archive of the folder and queue a task listing its files, which adds
+    /// a row for each, then uploads them one by one followed by the archive listing them.
+    /// Returns the archive row index.
+    pub fn start_folder<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        dir: &Path,
+    ) -> Result<usize, String> {
+        let folder = dir
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string();
+        // Archives list the addresses of their files, which private files don't have
+        if self.private {
+            return Err(format!(
+                "Folder {} can't be uploaded privately, drop its files instead",
+                dir.display()
+            ));
+        }
+        // and their file names in plain text, which a passphrase wouldn't protect
+        if self.passphrase.is_some() {
+            return Err(format!(
+                "Folder {} can't be uploaded with a passphrase, drop its files instead",
+                dir.display()
+            ));
+        }
+
+        let mut archive_status = UploadStatus::new(&folder);
+        archive_status.group = Some(self.new_group());
+        archive_status.is_archive = true;
+        archive_status.source = Some(dir.to_path_buf());
+        self.statuses.push(archive_status);
+
+        let archive_index = self.statuses.len() - 1;
+        let listing = Some(dir.to_path_buf());
+        self.queue_group(server, archive_index, folder, Vec::new(), listing, true);
+        Ok(archive_index)
+    }
+
+    /// Add a row for every file and one paying for them all at once, then queue a task encrypting
+    /// the files and paying for them, which queues every file paid for as its own upload.
+    /// A single file is uploaded on its own. Returns the paying row index, or the row of the
+    /// single file.
+    pub fn start_files<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        paths: &[PathBuf],
+    ) -> Option<usize> {
+        let [first, others @ ..] = paths else {
+            return None;
+        };
+        if others.is_empty() {
+            return Some(self.start(server, first));
+        }
+
+        let name = |path: &Path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("unknown")
+                .to_string()
+        };
+        let batch = format!("{} and {} more files", name(first), others.len());
+        let group = self.new_group();
+        let mut rows = Vec::new();
+        for path in paths {
+            let mut status = UploadStatus::new(&name(path));
+            status.group = Some(group);
+            status.source = Some(path.clone());
+            status.private = self.private;
+            status.passphrase = self.passphrase.clone();
+            self.statuses.push(status);
+            rows.push(FolderFile {
+                index: self.statuses.len() - 1,
+                path: path.clone(),
+                archive_path: PathBuf::from(name(path)),
+                address: None,
+                sealed: None,
+            });
+        }
+
+        let mut batch_status = UploadStatus::new(&batch);
+        batch_status.group = Some(group);
+        batch_status.is_batch = true;
+        batch_status.source = Some(first.clone());
+        batch_status.private = self.private;
+        batch_status.passphrase = self.passphrase.clone();
+        self.statuses.push(batch_status);
+
+        let batch_index = self.statuses.len() - 1;
+        self.queue_group(server, batch_index, batch, rows, None, false);
+        Some(batch_index)
+    }
+
+    fn new_group(&mut self) -> usize {
+        self.next_group += 1;
+        self.next_group - 1
+    }
+
+    /// Rows in the order they are shown, the rows of a group together where the group started,
+    /// its files followed by the row leading it
+    pub fn display_order(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        for (index, status) in self.statuses.iter().enumerate() {
+            let Some(group) = status.group else {
+                order.push(index);
+                continue;
+            };
+            if order
+                .iter()
+                .any(|&row| self.statuses[row].group == Some(group))
+            {
+                continue;
+            }
+            let rows: Vec<_> = (index..self.statuses.len())
+                .filter(|&row| self.statuses[row].group == Some(group))
+                .collect();
+            let (leads, files): (Vec<_>, Vec<_>) = rows
+                .into_iter()
+                .partition(|&row| self.statuses[row].leads_group());
+            order.extend(files);
+            order.extend(leads);
+        }
+        order
+    }
+
+    /// Name of the group, the one of the row leading it
+    pub fn group_name(&self, group: usize) -> Option<&str> {
+        self.statuses
+            .iter()
+            .find(|status| status.leads_group() && status.group == Some(group))
+            .map(|status| status.filename.as_str())
+    }
+
+    /// Upload the failed row again in place, reusing the payment cached when it failed.
+    /// Retrying a folder archive uploads its failed files again, then the archive.
+    pub fn retry<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        index: usize,
+    ) -> Result<(), String> {
+        let status = self
+            .statuses
+            .get(index)
+            .ok_or_else(|| format!("No upload at row {index}"))?;
+        if status.success != Some(false) {
+            return Err(format!("{} has not failed", status.filename));
+        }
+        self.requeue(server, index)
+    }
+
+    /// Queue the paused upload again, reusing the payment made before it was paused
+    pub fn resume<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        index: usize,
+    ) -> Result<(), String> {
+        let status = self
+            .statuses
+            .get(index)
+            .ok_or_else(|| format!("No upload at row {index}"))?;
+        if status.state != UploadState::Paused {
+            return Err(format!("{} is not paused", status.filename));
+        }
+        self.requeue(server, index)
+    }
+
+    /// Stop the queued or running upload until it is resumed
+    pub fn pause(&mut self, index: usize) {
+        for row in self.stop(index) {
+            let status = &mut self.statuses[row];
+            status.state = UploadState::Paused;
+            status.message = format!("Paused {}", status.filename);
+        }
+    }
+
+    /// Stop the upload for good, it can still be retried with the payment made so far
+    pub fn cancel(&mut self, index: usize) {
+        for row in self.stop(index) {
+            let status = &mut self.statuses[row];
+            status.state = UploadState::Done;
+            status.success = Some(false);
+            status.time_to_complete = Some(status.start_time.elapsed().as_secs_f32());
+            status.message = format!("Cancelled {}", status.filename);
+        }
+    }
+
+    /// Remove the upload of the row from the queue or abort its task, freeing its slot.
+    /// Returns its unfinished rows.
+    fn stop(&mut self, index: usize) -> Vec<usize> {
+        if let Some(position) = self.queue.iter().position(|upload| upload.index == index) {
+            self.queue.remove(position);
+        }
+        if let Some(handle) = self.running.remove(&index) {
+            handle.abort();
+        }
+        let rows = self.upload_rows(index);
+        for row in &rows {
+            self.approvals.remove(row);
+            self.statuses[*row].awaiting_approval = false;
+        }
+        self.start_queued();
+        rows
+    }
+
+    /// Unfinished rows of the upload reporting to the row, including the files of a folder
+    fn upload_rows(&self, index: usize) -> Vec<usize> {
+        let Some(status) = self.statuses.get(index) else {
+            return Vec::new();
+        };
+        let in_upload = |row: usize, file: &UploadStatus| {
+            row == index
+                || (status.leads_group()
+                    && !file.leads_group()
+                    && file.group == status.group
+                    && !self.has_own_upload(row))
+        };
+        self.statuses
+            .iter()
+            .enumerate()
+            .filter(|&(row, file)| in_upload(row, file) && !file.is_done())
+            .map(|(row, _)| row)
+            .collect()
+    }
+
+    /// Whether the row is queued or running as an upload of its own
+    fn has_own_upload(&self, index: usize) -> bool {
+        self.running.contains_key(&index) || self.queue.iter().any(|upload| upload.index == index)
+    }
+
+    /// Whether the upload of the row can be paused or cancelled on its own: single files and
+    /// whole groups can, the files of a batch once it paid for them and queued their uploads
+    pub fn controllable(&self, index: usize) -> bool {
+        let Some(status) = self.statuses.get(index) else {
+            return false;
+        };
+        let Some(group) = status.group.filter(|_| !status.leads_group()) else {
+            return true;
+        };
+        self.statuses
+            .iter()
+            .any(|lead| lead.is_batch && lead.group == Some(group) && lead.is_done())
+    }
+
+    /// Whether an unfinished row uploads the content or the file a cached payment was made for,
+    /// retrying the payment would then start a second upload of it
+    pub fn is_uploading(&self, content_hash: &str, source: Option<&Path>) -> bool {
+        self.statuses
+            .iter()
+            .filter(|status| !status.is_done())
+            .any(|status| {
+                status.content_hash.as_deref() == Some(content_hash)
+                    || source.is_some_and(|source| {
+                        status.source.as_deref() == Some(source)
+                            || status
+                                .sealed
+                                .as_ref()
+                                .is_some_and(|sealed| sealed.path() == source)
+                    })
+            })
+    }
+
+    /// Restart the row and queue its upload, or the failed files of a group followed by its archive
+    fn requeue<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        index: usize,
+    ) -> Result<(), String> {
+        let status = &self.statuses[index];
+        let source = status
+            .source
+            .clone()
+            .ok_or_else(|| format!("Source of {} is unknown", status.filename))?;
+        let filename = status.filename.clone();
+        let archive = status.is_archive;
+        let group = status.group;
+
+        if !status.leads_group() {
+            self.statuses[index].restart();
+            self.queue_file(server, index, source, filename);
+            return Ok(());
+        }
+
+        // Files uploaded successfully are listed in the archive without uploading them again
+        let mut rows = Vec::new();
+        let own_uploads: Vec<_> = (0..self.statuses.len())
+            .filter(|&row| self.has_own_upload(row))
+            .collect();
+        for (file_index, file) in self.statuses.iter_mut().enumerate() {
+            if file.leads_group() || file.group != group || own_uploads.contains(&file_index) {
+                continue;
+            }
+            let Some(path) = file.source.clone() else {
+                continue;
+            };
+            let address = file.address().map(|address| address.to_string());
+            if address.is_none() {
+                file.restart();
+            }
+            rows.push(FolderFile {
+                index: file_index,
+                path,
+                archive_path: PathBuf::from(&file.filename),
+                address,
+                sealed: file.sealed.clone(),
+            });
+        }
+        // a folder stopped before its files were listed lists them again
+        let listing = (archive && rows.is_empty()).then_some(source);
+        self.statuses[index].restart();
+        self.queue_group(server, index, filename, rows, listing, archive);
+        Ok(())
+    }
+
+    /// Queue the task uploading the file, reporting to the row at `index`
+    fn queue_file<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        index: usize,
+        path: PathBuf,
+        filename: String,
+    ) {
+        let status_sender = self.sender.clone();
+        let server = server.clone();
+        let private = self.statuses[index].private;
+        let passphrase = self.statuses[index].passphrase.clone();
+        let sealed = self.statuses[index].sealed.clone();
+
+        let task = async move {
+            let start_time = Instant::now();
+            let progress = progress_reporter(index, status_sender.clone());
+            let prepared = match seal(&status_sender, index, &path, passphrase, sealed).await {
+                Ok(sealed) => {
+                    let path = sealed
+                        .as_ref()
+                        .map_or(path.as_path(), |sealed| sealed.path());
+                    prepare(
+                        &server,
+                        path,
+                        &filename,
+                        private,
+                        sealed.is_some(),
+                        &progress,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+            let result = match prepared {
+                Ok(prepared) => {
+                    send_prepared(&status_sender, index, &prepared);
+                    let approved = !prepared.needs_payment()
+                        || request_approval(&status_sender, index, prepared.quote(), 0).await;
+                    if approved {
+                        server.upload_prepared(prepared, &progress).await
+                    } else {
+                        Err("Payment rejected, nothing was paid".to_string())
+                    }
+                }
+                Err(e) => Err(e),
+            };
+            let event = upload_event(index, filename, start_time.elapsed(), result);
+            let _ = status_sender.send(event);
+        };
+        self.enqueue(index, Box::pin(task));
+    }
+
+    /// Queue the task paying at once for the files of the group that have no address yet,
+    /// after listing the files of the folder at `listing` if given.
+    /// The files of a folder are then uploaded one by one followed by the archive listing them
+    /// all if `archive` is set, the others are queued as uploads of their own.
+    /// Reports to the row leading the group at `group_index`.
+    fn queue_group<B: StorageBackend>(
+        &mut self,
+        server: &Server<B>,
+        group_index: usize,
+        name: String,
+        mut files: Vec<FolderFile>,
+        listing: Option<PathBuf>,
+        archive: bool,
+    ) {
+        let status_sender = self.sender.clone();
+        let server = server.clone();
+        let private = self.statuses[group_index].private;
+        let passphrase = self.statuses[group_index].passphrase.clone();
+
+        let task = async move {
+            let start_time = Instant::now();
+            if let Some(dir) = listing {
+                match list_folder(&status_sender, group_index, dir, &name).await {
+                    Ok(listed) => files = listed,
+                    Err(e) => {
+                        let event = upload_event(group_index, name, start_time.elapsed(), Err(e));
+                        let _ = status_sender.send(event);
+                        return;
+                    }
+                }
+            }
+            let mut entries = Vec::new();
+            let mut failures = 0;
+
+            // Encrypt and quote all the files first, so the whole group is approved and paid at once
+            let mut ready_files = Vec::new();
+            let mut prepared_files = Vec::new();
+            let mut separately = Quote::default();
+            let mut batched = Quote::default();
+            let mut needs_payment = false;
+            for file in files {
+                if let Some(address) = &file.address {
+                    entries.push(archive_entry(&file.path, file.archive_path, address));
+                    continue;
+                }
+
+                let file_start_time = Instant::now();
+                let filename = file.archive_path.to_string_lossy().to_string();
+                let progress = progress_reporter(file.index, status_sender.clone());
+                let sealed = file.sealed.clone();
+                let prepared = match seal(
+                    &status_sender,
+                    file.index,
+                    &file.path,
+                    passphrase.clone(),
+                    sealed,
+                )
+                .await
+                {
+                    Ok(sealed) => {
+                        let path = sealed.as_ref().map_or(file.path.as_path(), |s| s.path());
+                        prepare(
+                            &server,
+                            path,
+                            &filename,
+                            private,
+                            sealed.is_some(),
+                            &progress,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                match prepared {
+                    Ok(prepared) => {
+                        send_prepared(&status_sender, file.index, &prepared);
+                        if prepared.needs_payment() {
+                            needs_payment = true;
+                            separately = separately + prepared.quote();
+                            batched = batched.batched_with(prepared.quote());
+                        }
+                        ready_files.push((file, progress));
+                        prepared_files.push(prepared);
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        let event =
+                            upload_event(file.index, filename, file_start_time.elapsed(), Err(e));
+                        let _ = status_sender.send(event);
+                    }
+                }
+            }
+
+            let gas_saved = separately.gas.saturating_sub(batched.gas);
+            let approved = !needs_payment
+                || request_approval(&status_sender, group_index, batched, gas_saved).await;
+            let payment = if approved {
+                server.pay_prepared(&mut prepared_files).await
+            } else {
+                Err("Payment rejected, nothing was paid".to_string())
+            };
+
+            // files dropped together only share their payment, each is then uploaded on its own
+            if !archive {
+                for ((file, _), prepared) in ready_files.into_iter().zip(prepared_files) {
+                    let filename = file.archive_path.to_string_lossy().to_string();
+                    let event = match &payment {
+                        Ok(_) => UploadEvent::Paid {
+                            index: file.index,
+                            upload: upload_task(
+                                &server,
+                                &status_sender,
+                                file.index,
+                                filename,
+                                prepared,
+                            ),
+                        },
+                        Err(e) => {
+                            upload_event(file.index, filename, Duration::ZERO, Err(e.clone()))
+                        }
+                    };
+                    let _ = status_sender.send(event);
+                }
+                let result = payment.map(|paid| (String::new(), paid));
+                let event = upload_event(group_index, name, start_time.elapsed(), result);
+                let _ = status_sender.send(event);
+                return;
+            }
+
+            for ((file, progress), prepared) in ready_files.into_iter().zip(prepared_files) {
+                let file_start_time = Instant::now();
+                let filename = file.archive_path.to_string_lossy().to_string();
+                let result = match &payment {
+                    Ok(_) => server.upload_prepared(prepared, &progress).await,
+                    Err(e) => Err(e.clone()),
+                };
+                if let Ok((address, _)) = &result {
+                    entries.push(archive_entry(&file.path, file.archive_path, address));
+                }
+                if result.is_err() {
+                    failures += 1;
+                }
+                let event = upload_event(file.index, filename, file_start_time.elapsed(), result);
+                let _ = status_sender.send(event);
+            }
+
+            let result = match payment {
+                Err(e) => Err(e),
+                Ok(_) if failures > 0 => Err(format!(
+                    "{failures} files failed to upload, retry to upload them again"
+                )),
+                Ok(_) => server.put_archive(&entries, &name).await,
+            };
+            let event = upload_event(group_index, name, start_time.elapsed(), result);
+            let _ = status_sender.send(event);
+        };
+        self.enqueue(group_index, Box::pin(task));
+    }
+
+    fn enqueue(&mut self, index: usize, task: UploadTask) {
+        self.queue.push_back(QueuedUpload { index, task });
+        self.start_queued();
+    }
+
+    /// Spawn queued uploads until `max_concurrent` are running
+    fn start_queued(&mut self) {
+        while self.running.len() < self.max_concurrent.max(1) {
+            let Some(upload) = self.queue.pop_front() else {
+                break;
+            };
+            for row in self.upload_rows(upload.index) {
+                self.statuses[row].run();
+            }
+            let handle = tokio::spawn(upload.task).abort_handle();
+            self.running.insert(upload.index, handle);
+        }
+    }
+
+    /// Rows of the queued uploads, in the order they will start
+    pub fn queued(&self) -> Vec<usize> {
+        self.queue.iter().map(|upload| upload.index).collect()
+    }
+
+    /// Move the queued upload of the row to the position in the queue, the last if past the end
+    pub fn move_queued(&mut self, index: usize, position: usize) {
+        let Some(from) = self.queue.iter().position(|upload| upload.index == index) else {
+            return;
+        };
+        if let Some(upload) = self.queue.remove(from) {
+            let position = position.min(self.queue.len());
+            self.queue.insert(position, upload);
+        }
+    }
+
+    /// Row index of the first upload waiting for its payment to be approved
+    pub fn awaiting_approval(&self) -> Option<usize> {
+        self.approvals.keys().next().copied()
+    }
+
+    /// Approve or reject paying for the upload at the row index
+    pub fn approve(&mut self, index: usize, approved: bool) {
+        if let Some(approval) = self.approvals.remove(&index) {
+            let _ = approval.send(approved);
+        }
+        if let Some(status) = self.statuses.get_mut(index) {
+            status.awaiting_approval = false;
+        }
+    }
+
+    /// Apply the events received from upload tasks so far, returns the rows that completed
+    pub fn process_events(&mut self) -> Vec<usize> {
+        self.start_queued();
+        let mut completed = Vec::new();
+        while let Ok(event) = self.receiver.try_recv() {
+            completed.extend(self.apply(event));
+        }
+        completed
+    }
+
+    /// Wait for the next event from upload tasks and apply it, returns the row if it completed
+    pub async fn next_event(&mut self) -> Option<usize> {
+        let event = self.receiver.recv().await?;
+        self.apply(event)
+    }
+
+    /// Apply an event to its row, returns the row if it completed.
+    /// The next queued uploads start once a running one completes.
+    pub fn apply(&mut self, event: UploadEvent) -> Option<usize> {
+        let completed = self.apply_to_status(event);
+        if completed.is_some_and(|index| self.running.remove(&index).is_some()) {
+            self.start_queued();
+        }
+        completed
+    }
+
+    /// Events of rows no longer running, e.g. sent just before the row was paused or cancelled,
+    /// are ignored
+    fn apply_to_status(&mut self, event: UploadEvent) -> Option<usize> {
+        match event {
+            UploadEvent::Complete {
+                index,
+                address,
+                cost,
+                duration,
+                filename,
+            } => {
+                let status = running(&mut self.statuses, index)?;
+                status.state = UploadState::Done;
+                status.success = Some(true);
+                status.time_to_complete = Some(duration.as_secs_f32());
+                status.message = if address.is_empty() {
+                    format!(
+                        "{filename} were paid for in {} seconds for {cost}!",
+                        duration.as_secs_f32(),
+                    )
+                } else if status.already_stored {
+                    format!(
+                        "{filename} was already on the network, nothing to pay! At address: {address}"
+                    )
+                } else {
+                    format!(
+                        "{filename} was successfully uploaded in {} seconds for {cost}! At address: {address}",
+                        duration.as_secs_f32(),
+                    )
+                };
+                status.cost = Some(cost);
+                status.address = Some(address).filter(|address| !address.is_empty());
+                Some(index)
+            }
+            UploadEvent::Failed {
+                index,
+                filename,
+                duration,
+                error,
+            } => {
+                let status = running(&mut self.statuses, index)?;
+                status.state = UploadState::Done;
+                status.success = Some(false);
+                status.time_to_complete = Some(duration.as_secs_f32());
+                status.message = format!("Failed to upload {}: {}", filename, error);
+                Some(index)
+            }
+            UploadEvent::Progress { index, progress } => {
+                if let Some(status) = running(&mut self.statuses, index) {
+                    if progress.paid == progress.total && status.storing_since.is_none() {
+                        status.storing_since = Some(Instant::now());
+                    }
+                    status.progress = Some(progress);
+                }
+                None
+            }
+            UploadEvent::Prepared {
+                index,
+                size,
+                content_hash,
+                already_stored,
+            } => {
+                if let Some(status) = running(&mut self.statuses, index) {
+                    status.size = Some(size);
+                    status.content_hash = Some(content_hash);
+                    status.already_stored = already_stored;
+                }
+                None
+            }
+            UploadEvent::Sealed { index, sealed } => {
+                // kept even once stopped, a payment may already be made for these bytes
+                if let Some(status) = self.statuses.get_mut(index) {
+                    status.sealed = Some(sealed);
+                }
+                None
+            }
+            UploadEvent::Quoted {
+                index,
+                quote,
+                gas_saved,
+                approval,
+            } => {
+                // dropping the approval of a stopped row rejects the payment
+                if let Some(status) = running(&mut self.statuses, index) {
+                    status.quote = Some(quote);
+                    status.gas_saved = (gas_saved > 0).then_some(gas_saved);
+                    if let Some(max) = self.max_upload_cost.filter(|max| quote.ant() > *max) {
+                        warn!("Refusing payment of {} ANT above {max} ANT", quote.ant());
+                        let _ = approval.send(false);
+                    } else if self.auto_approve_below.is_some_and(|max| quote.ant() < max) {
+                        info!("Auto-approving payment of {} ANT", quote.ant());
+                        let _ = approval.send(true);
+                    } else {
+                        status.awaiting_approval = true;
+                        self.approvals.insert(index, approval);
+                    }
+                }
+                None
+            }
+            UploadEvent::Listed { index, files, rows } => {
+                // rows of a folder stopped while being listed are never added
+                let archive = self.statuses.get(index)?;
+                if archive.state != UploadState::Running || rows.is_closed() {
+                    return None;
+                }
+                let group = archive.group;
+                let mut indices = Vec::new();
+                for (path, archive_path) in files {
+                    let mut status = UploadStatus::new(&archive_path.to_string_lossy());
+                    status.group = group;
+                    status.source = Some(path);
+                    status.run();
+                    self.statuses.push(status);
+                    indices.push(self.statuses.len() - 1);
+                }
+                let _ = rows.send(indices);
+                None
+            }
+            UploadEvent::Paid { index, upload } => {
+                // a batch stopped while paying leaves its files stopped, their payment is cached
+                let status = self.statuses.get_mut(index)?;
+                if status.state == UploadState::Running {
+                    status.state = UploadState::Queued;
+                    status.message = format!("Queued {}", status.filename);
+                    self.enqueue(index, upload);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// The row at the index, if its upload is running
+fn running(statuses: &mut [UploadStatus], index: usize) -> Option<&mut UploadStatus> {
+    statuses
+        .get_mut(index)
+        .filter(|status| status.state == UploadState::Running)
+}
+
+fn upload_event(
+    index: usize,
+    filename: String,
+    duration: Duration,
+    result: Result<(String, String), String>,
+) -> UploadEvent {
+    match result {
+        Ok((address, cost)) => UploadEvent::Complete {
+            index,
+            address,
+            cost,
+            duration,
+            filename,
+        },
+        Err(error) => UploadEvent::Failed {
+            index,
+            filename,
+            duration,
+            error,
+        },
+    }
+}
+
+/// Task paying for the prepared file if not paid yet and uploading it,
+/// reporting to the row at `index`
+fn upload_task<B: StorageBackend>(
+    server: &Server<B>,
+    sender: &mpsc::UnboundedSender<UploadEvent>,
+    index: usize,
+    filename: String,
+    prepared: PreparedUpload,
+) -> UploadTask {
+    let (server, sender) = (server.clone(), sender.clone());
+    Box::pin(async move {
+        let start_time = Instant::now();
+        let progress = progress_reporter(index, sender.clone());
+        let result = server.upload_prepared(prepared, &progress).await;
+        let _ = sender.send(upload_event(index, filename, start_time.elapsed(), result));
+    })
+}
+
+/// Copy of the file encrypted with the passphrase to upload in its place, if it has one.
+/// A copy sealed by a previous attempt is reused, so its cached payment still applies.
+async fn seal(
+    sender: &mpsc::UnboundedSender<UploadEvent>,
+    index: usize,
+    path: &Path,
+    passphrase: Option<Zeroizing<String>>,
+    sealed: Option<Arc<SealedFile>>,
+) -> Result<Option<Arc<SealedFile>>, String> {
+    match (sealed, passphrase) {
+        (Some(sealed), _) => Ok(Some(sealed)),
+        (None, Some(passphrase)) => {
+            info!("Encrypting {} with its passphrase...", path.display());
+            let sealed = Arc::new(SealedFile::seal(path, &passphrase).await?);
+            let _ = sender.send(UploadEvent::Sealed {
+                index,
+                sealed: sealed.clone(),
+            });
+            Ok(Some(sealed))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Encrypt and quote the file, keeping its data map private if asked to.
+/// `sealed` tells the file is a copy encrypted with a passphrase.
+async fn prepare<B: StorageBackend>(
+    server: &Server<B>,
+    path: &Path,
+    filename: &str,
+    private: bool,
+    sealed: bool,
+    progress: &(impl Fn(UploadProgress) + Sync),
+) -> Result<PreparedUpload, String> {
+    let mut prepared = if private {
+        server
+            .prepare_private_file(path, filename, progress)
+            .await?
+    } else {
+        server.prepare_file(path, filename, progress).await?
+    };
+    if sealed {
+        prepared.set_passphrase_encrypted();
+    }
+    Ok(prepared)
+}
+
+fn send_prepared(
+    sender: &mpsc::UnboundedSender<UploadEvent>,
+    index: usize,
+    prepared: &PreparedUpload,
+) {
+    let _ = sender.send(UploadEvent::Prepared {
+        index,
+        size: prepared.size(),
+        content_hash: prepared.content_hash().to_string(),
+        already_stored: prepared.is_stored(),
+    });
+}
+
+/// Ask for the payment of the quote to be approved, waits for the answer
+async fn request_approval(
+    sender: &mpsc::UnboundedSender<UploadEvent>,
+    index: usize,
+    quote: Quote,
+    gas_saved: u128,
+) -> bool {
+    let (approval, answer) = oneshot::channel();
+    let _ = sender.send(UploadEvent::Quoted {
+        index,
+        quote,
+        gas_saved,
+        approval,
+    });
+    answer.await.unwrap_or(false)
+}
+
+/// Callback sending the upload progress of the row at `index`
+fn progress_reporter(
+    index: usize,
+    sender: mpsc::UnboundedSender<UploadEvent>,
+) -> impl Fn(UploadProgress) + Send + Sync {
+    move |progress| {
+        let _ = sender.send(UploadEvent::Progress { index, progress });
+    }
+}
+
+/// List the files of the folder off the UI thread and have a row added for each,
+/// reporting to the archive row at `index`
+async fn list_folder(
+    sender: &mpsc::UnboundedSender<UploadEvent>,
+    index: usize,
+    dir: PathBuf,
+    folder: &str,
+) -> Result<Vec<FolderFile>, String> {
+    let listed_dir = dir.clone();
+    let paths = tokio::task::spawn_blocking(move || list_files(&listed_dir))
+        .await
+        .map_err(|e| format!("Listing task failed: {e}"))?
+        .map_err(|e| format!("Error reading folder {}: {e}", dir.display()))?;
+    if paths.is_empty() {
+        return Err(format!("Folder {} has no files to upload", dir.display()));
+    }
+
+    // Files are listed in the archive under the folder name, as the ant CLI does
+    let files: Vec<_> = paths
+        .into_iter()
+        .map(|path| {
+            let archive_path = Path::new(folder).join(path.strip_prefix(&dir).unwrap_or(&path));
+            (path, archive_path)
+        })
+        .collect();
+    let (rows, answer) = oneshot::channel();
+    let _ = sender.send(UploadEvent::Listed {
+        index,
+        files: files.clone(),
+        rows,
+    });
+    let rows = answer
+        .await
+        .map_err(|_| "Stopped while listing the folder".to_string())?;
+    Ok(files
+        .into_iter()
+        .zip(rows)
+        .map(|((path, archive_path), index)| FolderFile {
+            index,
+            path,
+            archive_path,
+            address: None,
+            sealed: None,
+        })
+        .collect())
+}
+
+/// All the files under the directory, recursively, in a stable order.
+/// Symlinked files are listed, symlinked directories are skipped so links looping back
+/// to a parent directory are never followed.
+fn list_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    for entry in entries {
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            files.extend(list_files(&path)?);
+        } else if file_type.is_file() || (file_type.is_symlink() && path.is_file()) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn archive_entry(path: &Path, archive_path: PathBuf, address: &str) -> ArchiveEntry {
+    let metadata = std::fs::metadata(path).ok();
+    let secs = |time: Option<std::io::Result<SystemTime>>| {
+        time?
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs())
+    };
+    let modified = secs(metadata.as_ref().map(|m| m.modified())).unwrap_or_default();
+    let created = secs(metadata.as_ref().map(|m| m.created())).unwrap_or(modified);
+    ArchiveEntry {
+        path: archive_path,
+        address: address.to_string(),
+        size: metadata.map(|m| m.len()).unwrap_or_default(),
+        created,
+        modified,
+    }
+}
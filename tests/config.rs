@@ -0,0 +1,121 @@
+//! Load, validate and save the TOML config.
+
+use ant_upload::config::{Config, LogLevel};
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn config_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "ant_upload_config_{name}_{}.toml",
+        std::process::id()
+    ))
+}
+
+#[test]
+fn missing_settings_take_their_default() {
+    let config = Config::parse("max_concurrent = 8\nlog_level = \"debug\"\n").unwrap();
+    assert_eq!(config.max_concurrent, 8);
+    assert_eq!(config.log_level, LogLevel::Debug);
+    assert_eq!(config.environment, Config::default().environment);
+    assert_eq!(config.payment_expiry(), Duration::from_secs(30 * 86400));
+
+    let missing = Config::load_from(&config_path("missing")).unwrap();
+    assert_eq!(missing, Config::default());
+}
+
+#[test]
+fn config_is_saved_and_loaded_back() {
+    let path = config_path("saved");
+    let config = Config {
+        environment: "local".to_string(),
+        retries: 5,
+        retry_delay_secs: 1,
+        auto_approve_below: Some(0.5),
+        max_upload_cost: Some(2.0),
+        ..Default::default()
+    };
+    config.save_to(&path).unwrap();
+    let loaded = Config::load_from(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(loaded, config);
+    assert_eq!(loaded.retry_policy().retries, 5);
+    assert_eq!(loaded.retry_policy().initial_delay, Duration::from_secs(1));
+}
+
+#[test]
+fn invalid_config_is_refused_with_the_setting_at_fault() {
+    let error = |text: &str| Config::parse(text).unwrap_err();
+    assert!(error("environment = \"mainnet\"").contains("environment must be one of"));
+    assert!(error("max_concurrent = 0").contains("max_concurrent"));
+    assert!(error("payment_expiry_days = 29").contains("payment_expiry_days"));
+    assert!(error("payment_expiry_days = 366").contains("payment_expiry_days"));
+    assert!(error("retry_delay_secs = 601").contains("retry_delay_secs"));
+    assert!(error("max_upload_cost = -1.0").contains("max_upload_cost"));
+    assert!(error("auto_approve_below = 2.0\nmax_upload_cost = 1.0").contains("can't be above"));
+    assert!(error("log_level = \"loud\"").contains("log_level"));
+    assert!(error("retrys = 3").contains("retrys"));
+
+    // the file is named in the error
+    let path = config_path("invalid");
+    std::fs::write(&path, "retries = \"three\"").unwrap();
+    let error = Config::load_from(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert!(error.contains(&path.display().to_string()), "{error}");
+
+    let invalid = Config {
+        retries: 11,
+        ..Default::default()
+    };
+    assert!(invalid.save_to(&config_path("unsaved")).is_err());
+    assert!(!config_path("unsaved").exists());
+}
+
+const TESTNET: &str = r#"
+environment = "testnet"
+
+[[networks]]
+name = "testnet"
+bootstrap_peers = ["/ip4/10.0.0.1/udp/5000/quic-v1/p2p/12D3KooWRBhwfeP2Y4TCx1SM6s9rUoHhR5STiGwxBhgFRcw3UERE"]
+evm_rpc_url = "http://10.0.0.1:8545"
+payment_token_address = "0x5FbDB2315678afecb367f032d93F642f64180aa3"
+vault_address = "0x8464135c8F25Da09e49BC8782676a84730C318bC"
+network_id = 7
+"#;
+
+#[test]
+fn configured_networks_are_selectable_environments() {
+    let config = Config::parse(TESTNET).unwrap();
+    assert_eq!(
+        config.environments(),
+        vec!["local", "autonomi", "alpha", "testnet"]
+    );
+    let network = config.network("testnet").unwrap();
+    assert_eq!(network.network_id, Some(7));
+    assert_eq!(network.bootstrap_peers.len(), 1);
+    assert!(config.network("alpha").is_none());
+
+    let path = config_path("networks");
+    config.save_to(&path).unwrap();
+    let loaded = Config::load_from(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded, config);
+}
+
+#[test]
+fn invalid_networks_are_refused() {
+    let error = |from: &str, to: &str| Config::parse(&TESTNET.replace(from, to)).unwrap_err();
+    assert!(error("name = \"testnet\"", "name = \"alpha\"").contains("built-in"));
+    assert!(error("/ip4/10.0.0.1", "10.0.0.1").contains("bootstrap peer"));
+    assert!(error("http://10.0.0.1:8545", "10.0.0.1:8545").contains("evm_rpc_url"));
+    assert!(error("0x5FbDB", "5FbDB").contains("payment_token_address"));
+    assert!(error("0x8464135c", "0x8464135").contains("vault_address"));
+    // the selected environment must exist
+    assert!(error("environment = \"testnet\"", "environment = \"other\"").contains("testnet"));
+
+    let twice = format!(
+        "{TESTNET}{}",
+        &TESTNET[TESTNET.find("[[networks]]").unwrap()..]
+    );
+    assert!(Config::parse(&twice).unwrap_err().contains("defined twice"));
+}
@@ -0,0 +1,56 @@
+//! Files encrypted by streaming them from disk have the chunks and address autonomi gives them.
+
+use ant_upload::cached_payments;
+use ant_upload::encryption::EncryptedFile;
+use autonomi::Bytes;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+fn create_file(name: &str, content: &[u8]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ant_upload_tests_{}", std::process::id()));
+    cached_payments::set_client_data_dir(dir);
+    let path = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+    std::fs::write(&path, content).expect("write file");
+    path
+}
+
+/// Bytes that don't repeat, so no two chunks are the same
+fn content(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+async fn assert_encrypted_like_autonomi(name: &str, len: usize) {
+    let content = content(len);
+    let path = create_file(name, &content);
+    let file = EncryptedFile::encrypt(&path).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let (data_map_chunk, chunks) =
+        autonomi::self_encryption::encrypt(Bytes::from(content)).unwrap();
+    assert_eq!(file.address().xorname(), data_map_chunk.name());
+    let expected: HashSet<_> = chunks
+        .iter()
+        .chain([&data_map_chunk])
+        .map(|chunk| *chunk.name())
+        .collect();
+    let encrypted: HashSet<_> = file.chunks().iter().map(|(name, _)| *name).collect();
+    assert_eq!(encrypted, expected);
+}
+
+#[tokio::test]
+async fn small_files_have_the_address_autonomi_gives_them() {
+    assert_encrypted_like_autonomi("small", 1000).await;
+}
+
+#[tokio::test]
+async fn multi_chunk_files_have_the_address_autonomi_gives_them() {
+    assert_encrypted_like_autonomi("multi_chunk", 10 * 1024 * 1024 + 123).await;
+}
@@ -0,0 +1,103 @@
+//! Upload history saved to disk and loaded back.
+
+use ant_upload::history::{History, HistoryEntry};
+use ant_upload::uploads::{UploadState, UploadStatus};
+
+use std::path::PathBuf;
+
+fn history_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{name}_{}_history.json", std::process::id()))
+}
+
+fn entry(filename: &str, address: &str) -> HistoryEntry {
+    HistoryEntry {
+        filename: filename.to_string(),
+        size: 42,
+        content_hash: sha256::digest(filename),
+        address: address.to_string(),
+        cost: "42".to_string(),
+        environment: "local".to_string(),
+        timestamp: 0,
+        private: false,
+        passphrase: false,
+    }
+}
+
+#[test]
+fn history_is_saved_and_loaded_back() {
+    let path = history_path("saved");
+    let mut history = History::load_from(path.clone()).unwrap();
+    assert!(history.entries().is_empty());
+
+    history.add(entry("report.pdf", "aa11")).unwrap();
+    history.add(entry("photo.jpg", "bb22")).unwrap();
+    history.remove(0).unwrap();
+
+    let loaded = History::load_from(path.clone()).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(loaded.entries().len(), 1);
+    assert_eq!(loaded.entries()[0].filename, "photo.jpg");
+    assert!(loaded.entries()[0].timestamp > 0);
+}
+
+#[test]
+fn history_search_matches_filename_and_address() {
+    let path = history_path("search");
+    let mut history = History::load_from(path.clone()).unwrap();
+    history.add(entry("Report.pdf", "aa11")).unwrap();
+    history.add(entry("photo.jpg", "bb22")).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    assert_eq!(history.search("report"), vec![0]);
+    assert_eq!(history.search("BB22"), vec![1]);
+    assert_eq!(history.search(""), vec![0, 1]);
+}
+
+#[test]
+fn upload_of_the_same_content_is_found_by_environment() {
+    let path = history_path("find");
+    let mut history = History::load_from(path.clone()).unwrap();
+    history.add(entry("report.pdf", "aa11")).unwrap();
+    history.add(entry("report.pdf", "bb22")).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    let content_hash = sha256::digest("report.pdf");
+    let found = history.find_upload(&content_hash, "local").unwrap();
+    assert_eq!(found.address, "bb22");
+    assert!(history.find_upload(&content_hash, "autonomi").is_none());
+    assert!(history.find_upload("", "local").is_none());
+}
+
+#[test]
+fn private_data_map_is_exported_for_sharing() {
+    let dir = std::env::temp_dir();
+    let public = entry("public.txt", "aa11");
+    assert!(public.export_data_map(&dir).is_err());
+
+    let filename = format!("private_{}.txt", std::process::id());
+    let private = HistoryEntry {
+        private: true,
+        ..entry(&filename, "0123abcd")
+    };
+    let path = private.export_data_map(&dir).unwrap();
+    let exported = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(path, dir.join(format!("{filename}.datamap")));
+    assert_eq!(exported, "0123abcd");
+}
+
+#[test]
+fn history_entries_are_listed_as_completed_uploads() {
+    let mut recorded = entry("report.pdf", "aa11");
+    recorded.timestamp = 1_700_000_000;
+    let mut archive = entry("photos", "bb22");
+    archive.content_hash = String::new();
+
+    let row = UploadStatus::from_history(&recorded);
+    assert_eq!(row.state, UploadState::Done);
+    assert_eq!(row.success, Some(true));
+    assert_eq!(row.address(), Some("aa11"));
+    assert_eq!(row.uploaded_at, Some(1_700_000_000));
+    assert!(!row.is_archive);
+    assert!(UploadStatus::from_history(&archive).is_archive);
+}
@@ -0,0 +1,91 @@
+//! Encrypt data with a passphrase and read it back.
+
+use ant_upload::Server;
+use ant_upload::backend::MemoryBackend;
+use ant_upload::{cached_payments, passphrase};
+use std::sync::Once;
+
+/// Keep the payments of the uploads out of the user's client data dir
+fn isolate_data_dir() {
+    static DATA_DIR: Once = Once::new();
+    DATA_DIR.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("ant_upload_tests_{}", std::process::id()));
+        cached_payments::set_client_data_dir(dir);
+    });
+}
+
+#[test]
+fn data_decrypts_only_with_its_passphrase() {
+    let sealed = passphrase::encrypt(b"hello world", "correct horse").unwrap();
+    assert!(passphrase::is_encrypted(&sealed));
+    assert!(!passphrase::is_encrypted(b"hello world"));
+    assert_eq!(
+        passphrase::decrypt(&sealed, "correct horse").unwrap(),
+        b"hello world"
+    );
+    assert!(passphrase::decrypt(&sealed, "wrong horse").is_err());
+    assert!(passphrase::decrypt(b"hello world", "correct horse").is_err());
+}
+
+#[test]
+fn altered_data_fails_to_decrypt() {
+    let mut sealed = passphrase::encrypt(b"hello world", "correct horse").unwrap();
+    let last = sealed.len() - 1;
+    sealed[last] ^= 1;
+    assert!(passphrase::decrypt(&sealed, "correct horse").is_err());
+
+    // the algorithm recorded in the header must be known
+    let mut sealed = passphrase::encrypt(b"hello world", "correct horse").unwrap();
+    sealed[7] = 42;
+    let error = passphrase::decrypt(&sealed, "correct horse").unwrap_err();
+    assert!(error.contains("Unsupported"), "{error}");
+}
+
+#[test]
+fn key_derivation_costs_above_the_defaults_are_refused() {
+    let mut sealed = passphrase::encrypt(b"hello world", "correct horse").unwrap();
+    // memory cost right after the magic, version and algorithm
+    sealed[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+    let error = passphrase::decrypt(&sealed, "correct horse").unwrap_err();
+    assert!(error.contains("too high"), "{error}");
+}
+
+#[test]
+fn data_of_several_segments_decrypts_only_whole() {
+    for len in [64 * 1024, 200 * 1024] {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let sealed = passphrase::encrypt(&data, "correct horse").unwrap();
+        assert_eq!(passphrase::decrypt(&sealed, "correct horse").unwrap(), data);
+
+        // dropping the whole last segment and its tag is detected too
+        let last_segment = len % (64 * 1024) + 16;
+        let truncated = &sealed[..sealed.len() - last_segment];
+        assert!(passphrase::decrypt(truncated, "correct horse").is_err());
+    }
+}
+
+#[tokio::test]
+async fn data_put_with_a_passphrase_downloads_with_it() {
+    isolate_data_dir();
+    let server = Server::with_backend(MemoryBackend::default());
+    let (address, _) = server
+        .put_data_with_passphrase(b"hello world", "hello.txt", "correct horse")
+        .await
+        .unwrap();
+
+    let stored = server.get_data(&address).await.unwrap();
+    assert!(passphrase::is_encrypted(&stored));
+    assert_eq!(
+        server
+            .get_data_with_passphrase(&address, "correct horse")
+            .await
+            .unwrap(),
+        b"hello world"
+    );
+    assert!(
+        server
+            .get_data_with_passphrase(&address, "wrong horse")
+            .await
+            .is_err()
+    );
+}
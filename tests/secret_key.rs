@@ -0,0 +1,27 @@
+//! Keep the secret key out of the login text field once it is used.
+
+use ant_upload::Server;
+use ant_upload::backend::MemoryBackend;
+use ant_upload::secret_key::SecretKey;
+
+const KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+#[tokio::test]
+async fn text_field_is_empty_after_login() {
+    let mut text_field = format!(" {KEY}\n");
+    let secret_key = SecretKey::take_from(&mut text_field);
+    let server = Server::<MemoryBackend>::init(secret_key.expose(), "local")
+        .await
+        .unwrap();
+
+    assert!(text_field.is_empty());
+    assert_eq!(secret_key.expose(), KEY);
+    assert!(server.balance().await.is_ok());
+}
+
+#[test]
+fn secret_key_is_never_printed() {
+    let secret_key = SecretKey::from(KEY);
+    assert_eq!(format!("{secret_key:?}"), "SecretKey(***)");
+    assert!(SecretKey::default().expose().is_empty());
+}
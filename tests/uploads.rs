@@ -0,0 +1,1036 @@
+//! Drive the app's upload flow against the in-memory backend.
+
+use ant_upload::Server;
+use ant_upload::backend::{MemoryBackend, Quote, StorageBackend};
+use ant_upload::cached_payments;
+use ant_upload::encryption::EncryptedFile;
+use ant_upload::server::RetryPolicy;
+use ant_upload::uploads::{UploadEvent, UploadState, Uploads};
+
+use autonomi::client::ChunkBatchUploadState;
+
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+use zeroize::Zeroizing;
+
+/// Keep the cached payments of the tests in a data dir of their own, away from the user's
+fn isolate_data_dir() {
+    static DATA_DIR: Once = Once::new();
+    DATA_DIR.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("ant_upload_tests_{}", std::process::id()));
+        cached_payments::set_client_data_dir(dir);
+    });
+}
+
+/// Short unique filename so cached payments of concurrent tests never collide
+fn unique_filename(name: &str) -> String {
+    isolate_data_dir();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    format!("{name}_{}_{nanos}", std::process::id())
+}
+
+fn remove_cached_payments(filename: &str) {
+    let dir = cached_payments::get_payments_dir().expect("payments dir");
+    for entry in std::fs::read_dir(dir).expect("read payments dir").flatten() {
+        if entry.file_name().to_string_lossy().ends_with(filename) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// Write a file in the temp dir
+fn create_file(name: &str, content: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(name);
+    std::fs::write(&path, content).expect("write file");
+    path
+}
+
+/// Create a folder with nested files in the temp dir, each file containing its own path
+/// within the folder so their content is unique to the folder
+fn create_folder(name: &str) -> PathBuf {
+    let folder = unique_filename(name);
+    let dir = std::env::temp_dir().join(&folder);
+    std::fs::create_dir_all(dir.join("nested")).expect("create folder");
+    std::fs::write(dir.join("a.txt"), format!("{folder}/a.txt")).expect("write file");
+    let b = format!("{folder}/nested/b.txt");
+    std::fs::write(dir.join("nested").join("b.txt"), b).expect("write file");
+    dir
+}
+
+/// Pay for the chunks of the file and cache the payment under its name, as the ant CLI does
+async fn cache_cli_payment(backend: &MemoryBackend, file: &Path, filename: &str) {
+    let chunks = EncryptedFile::encrypt(file)
+        .await
+        .unwrap()
+        .chunks()
+        .to_vec();
+    let quote = backend.quote_chunks(&chunks).await.unwrap();
+    let (receipt, _) = backend.pay(&chunks, None, quote.tokens).await.unwrap();
+    let upload_state = ChunkBatchUploadState {
+        payment: Some(receipt),
+        ..Default::default()
+    };
+    cached_payments::save_payment(filename, &upload_state).unwrap();
+}
+
+/// Server failing uploads as soon as a chunk fails
+fn without_retries(backend: MemoryBackend) -> Server<MemoryBackend> {
+    Server::with_backend(backend).with_retry_policy(RetryPolicy {
+        retries: 0,
+        initial_delay: Duration::ZERO,
+    })
+}
+
+/// Uploads paying for everything without asking for approval
+fn auto_approving_uploads() -> Uploads {
+    let mut uploads = Uploads::default();
+    uploads.auto_approve_below = Some(f64::INFINITY);
+    uploads
+}
+
+async fn wait_for_uploads(uploads: &mut Uploads) {
+    while uploads.has_active() {
+        tokio::time::timeout(Duration::from_secs(5), uploads.next_event())
+            .await
+            .expect("upload should finish");
+    }
+}
+
+#[tokio::test]
+async fn uploads_update_their_own_status_rows() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    let first_file = create_file(&unique_filename("a"), b"first file");
+    let second_file = create_file(&unique_filename("b"), b"second file");
+
+    let first = uploads.start(&server, &first_file);
+    let second = uploads.start(&server, &second_file);
+    assert!(uploads.has_active());
+
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(first_file).unwrap();
+    std::fs::remove_file(second_file).unwrap();
+
+    let first = &uploads.statuses[first];
+    let second = &uploads.statuses[second];
+    assert_eq!(first.success, Some(true));
+    assert_eq!(second.success, Some(true));
+    assert!(first.time_to_complete.is_some());
+
+    let first_address = first.address().expect("address of first upload");
+    let second_address = second.address().expect("address of second upload");
+    assert_ne!(first_address, second_address);
+    assert_eq!(server.get_data(first_address).await.unwrap(), b"first file");
+    assert_eq!(
+        server.get_data(second_address).await.unwrap(),
+        b"second file"
+    );
+    assert_eq!(backend.wallet_payments(), 2);
+    assert_eq!(first.size, Some(10));
+    assert_eq!(first.content_hash, Some(sha256::digest("first file")));
+}
+
+#[tokio::test]
+async fn addresses_are_not_read_from_the_status_message() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let mut uploads = auto_approving_uploads();
+    let filename = unique_filename("At address: nowhere");
+    let file = create_file(&filename, filename.as_bytes());
+
+    let index = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(file).unwrap();
+
+    let address = uploads.statuses[index].address().expect("address");
+    assert!(!address.contains("nowhere"), "{address}");
+    assert_eq!(server.get_data(address).await.unwrap(), filename.as_bytes());
+}
+
+#[tokio::test]
+async fn failed_upload_caches_payment_and_retry_reuses_it() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    let filename = unique_filename("retry");
+    let content = filename.repeat(2);
+    let content_hash = sha256::digest(&content);
+    let file = create_file(&filename, content.as_bytes());
+
+    backend.fail_next_puts(1);
+    let failed = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+
+    let status = &uploads.statuses[failed];
+    assert_eq!(status.success, Some(false));
+    assert!(status.address().is_none());
+    assert!(status.message.contains(&filename));
+    let cached = cached_payments::load_payment_for_content(&content_hash, "other name").unwrap();
+    assert!(
+        cached.is_some(),
+        "payment should be cached after a batch failure"
+    );
+
+    // the payment follows the content, not the name
+    let renamed = create_file(&unique_filename("renamed"), content.as_bytes());
+    let retried = uploads.start(&server, &renamed);
+    wait_for_uploads(&mut uploads).await;
+    remove_cached_payments(&content_hash);
+    std::fs::remove_file(file).unwrap();
+    std::fs::remove_file(renamed).unwrap();
+
+    assert_eq!(uploads.statuses[retried].success, Some(true));
+    assert_eq!(backend.wallet_payments(), 1);
+    assert_eq!(backend.receipt_payments(), 1);
+}
+
+#[tokio::test]
+async fn retry_uploads_the_failed_row_in_place_with_its_payment() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    let filename = unique_filename("retried");
+    let file = create_file(&filename, filename.as_bytes());
+
+    backend.fail_next_puts(1);
+    let index = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    assert_eq!(uploads.statuses[index].success, Some(false));
+
+    uploads.retry(&server, index).unwrap();
+    assert!(uploads.has_active());
+    wait_for_uploads(&mut uploads).await;
+    remove_cached_payments(&sha256::digest(&filename));
+    std::fs::remove_file(file).unwrap();
+
+    assert_eq!(uploads.statuses.len(), 1);
+    assert_eq!(uploads.statuses[index].success, Some(true));
+    assert!(uploads.retry(&server, index).is_err());
+    assert_eq!(backend.wallet_payments(), 1);
+    assert_eq!(backend.receipt_payments(), 1);
+}
+
+#[tokio::test]
+async fn retrying_a_folder_uploads_its_failed_files_and_archive() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    let dir = create_folder("retried");
+    let folder = dir.file_name().unwrap().to_string_lossy().to_string();
+
+    backend.fail_next_puts(1);
+    let archive = uploads.start_folder(&server, &dir).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    assert_eq!(uploads.statuses[archive].success, Some(false));
+
+    uploads.retry(&server, archive).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    remove_cached_payments(&sha256::digest(format!("{folder}/a.txt")));
+
+    assert_eq!(uploads.statuses.len(), 3);
+    assert!(uploads.statuses.iter().all(|s| s.success == Some(true)));
+    // both files are paid for at once, the file stored the first time is not paid for again
+    assert_eq!(backend.wallet_payments(), 2);
+    assert_eq!(backend.receipt_payments(), 1);
+}
+
+#[tokio::test]
+async fn cached_payment_is_listed_with_its_file_and_can_be_deleted() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let filename = unique_filename("listed");
+    let file = create_file(&filename, filename.as_bytes());
+    let content_hash = sha256::digest(&filename);
+
+    backend.fail_next_puts(1);
+    assert!(server.put_file(&file, &filename).await.is_err());
+
+    let listed = cached_payments::list_cached_payments()
+        .unwrap()
+        .into_iter()
+        .find(|payment| payment.key == content_hash)
+        .expect("cached payment is listed");
+    assert_eq!(listed.source.as_deref(), Some(file.as_path()));
+    assert!(listed.expires_in().as_secs() > 0);
+
+    cached_payments::delete_cached_payment(&listed).unwrap();
+    std::fs::remove_file(file).unwrap();
+    let payments = cached_payments::list_cached_payments().unwrap();
+    assert!(payments.iter().all(|payment| payment.key != content_hash));
+}
+
+#[tokio::test]
+async fn cached_payment_of_a_private_upload_is_retried_privately() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    uploads.private = true;
+    let filename = unique_filename("private_retry");
+    let file = create_file(&filename, filename.as_bytes());
+    let content_hash = sha256::digest(&filename);
+
+    backend.fail_next_puts(1);
+    uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    let listed = cached_payments::list_cached_payments()
+        .unwrap()
+        .into_iter()
+        .find(|payment| payment.key == content_hash)
+        .expect("cached payment is listed");
+    assert!(listed.private);
+
+    // new uploads are public by now, the retry still uploads privately to reuse the payment
+    uploads.private = false;
+    let retried = uploads.retry_payment(&server, &listed).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(file).unwrap();
+
+    assert_eq!(uploads.statuses[retried].success, Some(true));
+    assert!(uploads.statuses[retried].private);
+    assert_eq!(backend.wallet_payments(), 1);
+}
+
+#[tokio::test]
+async fn payment_cached_by_the_ant_cli_under_the_filename_is_reused() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let filename = unique_filename("legacy");
+    let file = create_file(&filename, filename.as_bytes());
+    cache_cli_payment(&backend, &file, &filename).await;
+
+    let result = server.put_file(&file, &filename).await;
+    remove_cached_payments(&filename);
+    std::fs::remove_file(file).unwrap();
+
+    assert!(result.is_ok());
+    assert_eq!(backend.wallet_payments(), 1);
+    assert_eq!(backend.receipt_payments(), 1);
+}
+
+#[tokio::test]
+async fn payment_cached_by_the_ant_cli_for_other_content_is_not_reused() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let filename = unique_filename("legacy_other");
+    let file = create_file(&filename, b"the file the ant CLI paid for");
+    cache_cli_payment(&backend, &file, &filename).await;
+    std::fs::write(&file, b"another file with the same name").unwrap();
+
+    let result = server.put_file(&file, &filename).await;
+    remove_cached_payments(&filename);
+    std::fs::remove_file(file).unwrap();
+
+    assert!(result.is_ok());
+    assert_eq!(backend.wallet_payments(), 2);
+    assert_eq!(backend.receipt_payments(), 0);
+}
+
+#[tokio::test]
+async fn failed_chunks_are_retried_with_the_same_payment() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone()).with_retry_policy(RetryPolicy {
+        retries: 2,
+        initial_delay: Duration::from_millis(1),
+    });
+    let content: Vec<u8> = (0..64 * 1024).map(|i| (i % 239) as u8).collect();
+    let file = create_file(&unique_filename("flaky"), &content);
+
+    backend.fail_next_puts(2);
+    let (address, _cost) = server.put_file(&file, "flaky").await.unwrap();
+    std::fs::remove_file(file).unwrap();
+
+    assert_eq!(server.get_data(&address).await.unwrap(), content);
+    assert_eq!(backend.wallet_payments(), 1);
+    // the chunk failing in the first batch is the only one written again
+    assert_eq!(backend.chunk_writes(), backend.stored_chunks());
+}
+
+#[tokio::test]
+async fn large_file_is_stored_as_chunks_and_downloads_intact() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let content: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    let file = create_file(&unique_filename("large"), &content);
+
+    let (address, _cost) = server.put_file(&file, "large").await.unwrap();
+    std::fs::remove_file(file).unwrap();
+
+    assert!(backend.stored_chunks() > 1);
+    assert_eq!(server.get_data(&address).await.unwrap(), content);
+}
+
+#[tokio::test]
+async fn upload_rows_report_chunk_progress() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let mut uploads = auto_approving_uploads();
+    let content: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    let file = create_file(&unique_filename("progress"), &content);
+
+    let index = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    uploads.process_events();
+    std::fs::remove_file(file).unwrap();
+
+    let status = &uploads.statuses[index];
+    let progress = status.progress.expect("progress of the upload");
+    assert!(progress.total > 1);
+    assert_eq!(progress.quoted, progress.total);
+    assert_eq!(progress.paid, progress.total);
+    assert_eq!(progress.stored, progress.total);
+    assert!(status.storing_since.is_some());
+}
+
+#[tokio::test]
+async fn upload_waits_for_payment_approval() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = Uploads::default();
+    let approved_file = create_file(&unique_filename("approved"), b"approved file");
+    let rejected_file = create_file(&unique_filename("rejected"), b"rejected file");
+
+    let approved = uploads.start(&server, &approved_file);
+    let rejected = uploads.start(&server, &rejected_file);
+    while uploads.statuses.iter().any(|s| !s.awaiting_approval) {
+        uploads.next_event().await;
+    }
+    assert_eq!(backend.wallet_payments(), 0);
+    assert_eq!(uploads.awaiting_approval(), Some(approved));
+    let quote = uploads.statuses[approved].quote.expect("quote");
+    assert!(!quote.tokens.is_zero());
+
+    uploads.approve(approved, true);
+    uploads.approve(rejected, false);
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(approved_file).unwrap();
+    std::fs::remove_file(rejected_file).unwrap();
+
+    assert_eq!(uploads.awaiting_approval(), None);
+    assert_eq!(uploads.statuses[approved].success, Some(true));
+    assert_eq!(uploads.statuses[rejected].success, Some(false));
+    assert_eq!(backend.wallet_payments(), 1);
+}
+
+#[tokio::test]
+async fn payments_of_unfinished_uploads_are_not_offered_for_retry() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let mut uploads = Uploads::default();
+    let file = create_file(&unique_filename("unfinished"), b"unfinished upload");
+
+    let index = uploads.start(&server, &file);
+    while !uploads.statuses[index].awaiting_approval {
+        uploads.next_event().await;
+    }
+    let content_hash = uploads.statuses[index].content_hash.clone().expect("hash");
+    assert!(uploads.is_uploading(&content_hash, None));
+    assert!(uploads.is_uploading("other", Some(&file)));
+
+    uploads.approve(index, false);
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(&file).unwrap();
+
+    assert!(!uploads.is_uploading(&content_hash, Some(&file)));
+}
+
+#[tokio::test]
+async fn payments_priced_above_the_approved_quote_are_refused() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = Uploads::default();
+    let file = create_file(&unique_filename("repriced"), b"repriced upload");
+
+    let index = uploads.start(&server, &file);
+    while !uploads.statuses[index].awaiting_approval {
+        uploads.next_event().await;
+    }
+    // the network gets busier while the payment waits for approval
+    backend.raise_prices();
+    uploads.approve(index, true);
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(&file).unwrap();
+
+    let status = &uploads.statuses[index];
+    assert_eq!(status.success, Some(false));
+    assert!(status.message.contains("price rose"), "{}", status.message);
+    assert_eq!(backend.wallet_payments(), 0);
+}
+
+#[tokio::test]
+async fn events_of_stopped_uploads_are_ignored() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let mut uploads = Uploads::default();
+    let file = create_file(&unique_filename("stopped"), b"stopped upload");
+    let index = uploads.start(&server, &file);
+    uploads.cancel(index);
+    std::fs::remove_file(&file).unwrap();
+
+    // events sent by the task before it was aborted
+    let (approval, approved) = oneshot::channel();
+    uploads.apply(UploadEvent::Quoted {
+        index,
+        quote: Quote::default(),
+        gas_saved: 0,
+        approval,
+    });
+    uploads.apply(UploadEvent::Prepared {
+        index,
+        size: 14,
+        content_hash: "late".to_string(),
+        already_stored: false,
+    });
+    uploads.apply(UploadEvent::Complete {
+        index,
+        address: "late".to_string(),
+        cost: "0".to_string(),
+        duration: Duration::ZERO,
+        filename: "stopped".to_string(),
+    });
+
+    let status = &uploads.statuses[index];
+    assert!(!status.awaiting_approval);
+    assert_eq!(uploads.awaiting_approval(), None);
+    // the payment is rejected
+    assert!(approved.await.is_err());
+    assert_eq!(status.content_hash, None);
+    assert_eq!(status.success, Some(false));
+    assert_eq!(status.address(), None);
+}
+
+#[tokio::test]
+async fn queued_uploads_start_in_order_as_slots_free_up() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = Uploads::default();
+    uploads.max_concurrent = 1;
+    let files: Vec<_> = ["first", "second", "third"]
+        .iter()
+        .map(|name| create_file(&unique_filename(name), name.as_bytes()))
+        .collect();
+
+    let rows: Vec<_> = files
+        .iter()
+        .map(|file| uploads.start(&server, file))
+        .collect();
+    assert_eq!(uploads.statuses[rows[0]].state, UploadState::Running);
+    assert_eq!(uploads.queued(), vec![rows[1], rows[2]]);
+
+    // The third file jumps ahead of the second while the first waits for approval
+    uploads.move_queued(rows[2], 0);
+    assert_eq!(uploads.queued(), vec![rows[2], rows[1]]);
+    while uploads.awaiting_approval().is_none() {
+        uploads.next_event().await;
+    }
+    uploads.approve(rows[0], true);
+    while !uploads.statuses[rows[0]].is_done() {
+        uploads.next_event().await;
+    }
+    assert_eq!(uploads.statuses[rows[2]].state, UploadState::Running);
+    assert_eq!(uploads.statuses[rows[1]].state, UploadState::Queued);
+
+    uploads.auto_approve_below = Some(f64::INFINITY);
+    uploads.approve(rows[2], true);
+    wait_for_uploads(&mut uploads).await;
+    for file in files {
+        std::fs::remove_file(file).unwrap();
+    }
+
+    assert!(uploads.queued().is_empty());
+    assert!(uploads.statuses.iter().all(|s| s.success == Some(true)));
+    assert_eq!(backend.wallet_payments(), 3);
+}
+
+#[tokio::test]
+async fn paused_uploads_resume_and_cancelled_ones_can_be_retried() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = Uploads::default();
+    uploads.max_concurrent = 1;
+    let paused_file = create_file(&unique_filename("paused"), b"paused file");
+    let cancelled_file = create_file(&unique_filename("cancelled"), b"cancelled file");
+
+    let paused = uploads.start(&server, &paused_file);
+    let cancelled = uploads.start(&server, &cancelled_file);
+    while uploads.awaiting_approval().is_none() {
+        uploads.next_event().await;
+    }
+
+    // Pausing the running upload frees its slot for the queued one
+    uploads.pause(paused);
+    assert_eq!(uploads.statuses[paused].state, UploadState::Paused);
+    assert!(!uploads.statuses[paused].awaiting_approval);
+    assert_eq!(uploads.statuses[cancelled].state, UploadState::Running);
+
+    uploads.cancel(cancelled);
+    assert!(uploads.statuses[cancelled].is_done());
+    assert_eq!(uploads.statuses[cancelled].success, Some(false));
+    assert!(!uploads.has_active());
+    assert_eq!(backend.wallet_payments(), 0);
+
+    uploads.auto_approve_below = Some(f64::INFINITY);
+    uploads.resume(&server, paused).unwrap();
+    uploads.retry(&server, cancelled).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(paused_file).unwrap();
+    std::fs::remove_file(cancelled_file).unwrap();
+
+    assert_eq!(uploads.statuses[paused].success, Some(true));
+    assert_eq!(uploads.statuses[cancelled].success, Some(true));
+    assert_eq!(backend.wallet_payments(), 2);
+}
+
+#[tokio::test]
+async fn folders_with_the_same_name_are_paused_separately() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let mut uploads = Uploads::default();
+    let parent = std::env::temp_dir().join(unique_filename("same_name"));
+    let (first_dir, second_dir) = (parent.join("one/photos"), parent.join("two/photos"));
+    for dir in [&first_dir, &second_dir] {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("a.txt"), dir.to_string_lossy().as_bytes()).unwrap();
+    }
+
+    let first = uploads.start_folder(&server, &first_dir).unwrap();
+    let second = uploads.start_folder(&server, &second_dir).unwrap();
+    while uploads.statuses.len() < 4 {
+        uploads.next_event().await;
+    }
+    uploads.pause(first);
+    std::fs::remove_dir_all(parent).unwrap();
+
+    let (first, second) = (&uploads.statuses[first], &uploads.statuses[second]);
+    assert_eq!(first.filename, second.filename);
+    assert_ne!(first.group, second.group);
+    for status in &uploads.statuses {
+        let expected = if status.group == first.group {
+            UploadState::Paused
+        } else {
+            UploadState::Running
+        };
+        assert_eq!(status.state, expected);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_uploads_never_race_on_the_wallet_nonce() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let files: Vec<_> = (0..20)
+        .map(|i| {
+            let filename = unique_filename(&format!("nonce{i}"));
+            let file = create_file(&filename, filename.as_bytes());
+            (file, filename)
+        })
+        .collect();
+
+    // Files and data are all paid for at once from the same wallet
+    let mut tasks = Vec::new();
+    for (file, filename) in &files {
+        let (file_server, file, name) = (server.clone(), file.clone(), filename.clone());
+        tasks.push(tokio::spawn(async move {
+            file_server.put_file(&file, &name).await
+        }));
+        let (data_server, name) = (server.clone(), filename.clone());
+        tasks.push(tokio::spawn(async move {
+            let data = format!("{name} data");
+            data_server.put_data(data.as_bytes(), &name).await
+        }));
+    }
+    for task in tasks {
+        let result = task.await.expect("upload task");
+        assert!(result.is_ok(), "{result:?}");
+    }
+    for (file, _) in files {
+        std::fs::remove_file(file).unwrap();
+    }
+
+    assert_eq!(backend.wallet_payments(), 40);
+}
+
+#[tokio::test]
+async fn files_dropped_together_are_paid_for_at_once() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    let files: Vec<_> = (0..3)
+        .map(|i| {
+            let filename = unique_filename(&format!("batched{i}"));
+            create_file(&filename, filename.as_bytes())
+        })
+        .collect();
+
+    let batch = uploads.start_files(&server, &files).expect("batch row");
+    wait_for_uploads(&mut uploads).await;
+    for file in files {
+        std::fs::remove_file(file).unwrap();
+    }
+
+    assert_eq!(uploads.statuses.len(), 4);
+    assert!(uploads.statuses.iter().all(|s| s.success == Some(true)));
+    assert!(uploads.statuses[..3].iter().all(|s| s.address().is_some()));
+    let batch = &uploads.statuses[batch];
+    assert!(batch.is_batch);
+    assert!(batch.address().is_none());
+    // one payment instead of three, saving the gas of two transactions
+    assert_eq!(backend.wallet_payments(), 1);
+    let quote = batch.quote.expect("quote");
+    assert!(batch.gas_saved.is_some_and(|saved| saved == 2 * quote.gas));
+}
+
+#[tokio::test]
+async fn files_paid_for_at_once_are_queued_as_uploads_of_their_own() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    uploads.max_concurrent = 1;
+    let files: Vec<_> = (0..3)
+        .map(|i| {
+            let filename = unique_filename(&format!("own_upload{i}"));
+            create_file(&filename, filename.as_bytes())
+        })
+        .collect();
+
+    let batch = uploads.start_files(&server, &files).expect("batch row");
+    assert!(!uploads.controllable(0));
+    while !uploads.statuses[batch].is_done() {
+        uploads.next_event().await;
+    }
+
+    // once paid for, the files are reordered, paused and resumed one by one
+    assert_eq!(uploads.statuses[batch].success, Some(true));
+    assert_eq!(uploads.queued(), [1, 2]);
+    assert!(uploads.controllable(1));
+    uploads.move_queued(2, 0);
+    assert_eq!(uploads.queued(), [2, 1]);
+    uploads.pause(0);
+    wait_for_uploads(&mut uploads).await;
+    assert_eq!(uploads.statuses[0].state, UploadState::Paused);
+    assert_eq!(uploads.statuses[1].success, Some(true));
+    assert_eq!(uploads.statuses[2].success, Some(true));
+
+    uploads.resume(&server, 0).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    for file in files {
+        std::fs::remove_file(file).unwrap();
+    }
+    assert_eq!(uploads.statuses[0].success, Some(true));
+    assert_eq!(backend.wallet_payments(), 1);
+}
+
+#[tokio::test]
+async fn private_uploads_are_only_downloaded_with_their_data_map() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    uploads.private = true;
+    let filename = unique_filename("private");
+    let file = create_file(&filename, filename.as_bytes());
+
+    let index = uploads.start(&server, &file);
+    assert!(
+        uploads
+            .start_folder(&server, &std::env::temp_dir())
+            .is_err()
+    );
+    wait_for_uploads(&mut uploads).await;
+    let (public_address, _) = Server::with_backend(MemoryBackend::default())
+        .put_file(&file, &filename)
+        .await
+        .unwrap();
+    std::fs::remove_file(file).unwrap();
+
+    let status = &uploads.statuses[index];
+    assert!(status.private);
+    let data_map = status.address().expect("data map");
+    assert!(data_map.len() > 64);
+    assert_eq!(
+        server.get_data(data_map).await.unwrap(),
+        filename.as_bytes()
+    );
+    // the data map chunk was never stored, so the public address leads nowhere
+    assert!(server.get_data(&public_address).await.is_err());
+}
+
+#[tokio::test]
+async fn passphrase_uploads_are_retried_with_the_same_encrypted_copy() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    uploads.passphrase = Some(Zeroizing::new("correct horse".to_string()));
+    let filename = unique_filename("passphrase");
+    let file = create_file(&filename, filename.as_bytes());
+
+    backend.fail_next_puts(1);
+    let index = uploads.start(&server, &file);
+    // the archive of a folder would list its file names in plain text
+    assert!(
+        uploads
+            .start_folder(&server, &std::env::temp_dir())
+            .is_err()
+    );
+    wait_for_uploads(&mut uploads).await;
+    assert_eq!(uploads.statuses[index].success, Some(false));
+    let sealed = uploads.statuses[index].sealed.clone().expect("sealed copy");
+    let status = format!("{:?}", uploads.statuses[index]);
+    assert!(!status.contains("correct horse"), "{status}");
+
+    uploads.retry(&server, index).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    let content_hash = uploads.statuses[index].content_hash.clone().unwrap();
+    remove_cached_payments(&content_hash);
+    std::fs::remove_file(file).unwrap();
+
+    // the retry reused the encrypted copy, so the cached payment still applied
+    assert_eq!(backend.wallet_payments(), 1);
+    assert_eq!(backend.receipt_payments(), 1);
+    let address = uploads.statuses[index].address().expect("address");
+    assert_eq!(
+        server
+            .get_data_with_passphrase(address, "correct horse")
+            .await
+            .unwrap(),
+        filename.as_bytes()
+    );
+
+    // the encrypted copy is removed once no row holds it
+    let sealed_path = sealed.path().to_path_buf();
+    drop(sealed);
+    uploads.statuses.clear();
+    assert!(!sealed_path.exists());
+}
+
+#[tokio::test]
+async fn cached_payment_of_a_passphrase_upload_is_retried_from_its_encrypted_copy() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    uploads.passphrase = Some(Zeroizing::new("correct horse".to_string()));
+    let filename = unique_filename("passphrase_payment");
+    let file = create_file(&filename, filename.as_bytes());
+
+    backend.fail_next_puts(1);
+    uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    // the encrypted copy outlives its row while its payment is cached
+    uploads.statuses.clear();
+    let listed = cached_payments::list_cached_payments()
+        .unwrap()
+        .into_iter()
+        .find(|payment| payment.filename.as_deref() == Some(filename.as_str()))
+        .expect("cached payment is listed");
+    assert!(listed.passphrase);
+    let sealed_path = listed.source.clone().expect("source");
+    assert!(sealed_path.exists());
+
+    uploads.passphrase = None;
+    let retried = uploads.retry_payment(&server, &listed).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(file).unwrap();
+
+    let status = &uploads.statuses[retried];
+    assert_eq!(status.success, Some(true));
+    assert_eq!(status.filename, filename);
+    assert_eq!(backend.wallet_payments(), 1);
+    let address = status.address().expect("address");
+    assert_eq!(
+        server
+            .get_data_with_passphrase(address, "correct horse")
+            .await
+            .unwrap(),
+        filename.as_bytes()
+    );
+
+    // the payment is used up, so the copy goes with the row
+    uploads.statuses.clear();
+    assert!(!sealed_path.exists());
+}
+
+#[tokio::test]
+async fn content_already_on_the_network_is_not_paid_again() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = Uploads::default();
+    let filename = unique_filename("dedupe");
+    let file = create_file(&filename, filename.as_bytes());
+
+    uploads.auto_approve_below = Some(f64::INFINITY);
+    let first = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    // nothing to pay, so no approval is asked even when every payment needs one
+    uploads.auto_approve_below = None;
+    let second = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(file).unwrap();
+
+    let (first, second) = (&uploads.statuses[first], &uploads.statuses[second]);
+    assert!(!first.already_stored);
+    assert!(second.already_stored);
+    assert_eq!(second.success, Some(true));
+    assert!(second.message.contains("already on the network"));
+    assert_eq!(second.address(), first.address());
+    assert_eq!(backend.wallet_payments(), 1);
+    assert_eq!(uploads.awaiting_approval(), None);
+}
+
+#[tokio::test]
+async fn content_costing_no_tokens_is_still_uploaded() {
+    let backend = MemoryBackend::default();
+    backend.make_storage_free();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    let filename = unique_filename("free");
+    let file = create_file(&filename, filename.as_bytes());
+
+    let index = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(file).unwrap();
+
+    let status = &uploads.statuses[index];
+    assert_eq!(status.success, Some(true));
+    assert!(!status.already_stored);
+    assert_eq!(backend.wallet_payments(), 1);
+    let address = status.address().expect("address");
+    assert_eq!(server.get_data(address).await.unwrap(), filename.as_bytes());
+}
+
+#[tokio::test]
+async fn uploads_above_the_spending_limit_are_refused() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    uploads.max_upload_cost = Some(0.0);
+    let filename = unique_filename("limited");
+    let file = create_file(&filename, filename.as_bytes());
+
+    let index = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(file).unwrap();
+
+    assert_eq!(uploads.statuses[index].success, Some(false));
+    assert_eq!(uploads.awaiting_approval(), None);
+    assert_eq!(backend.wallet_payments(), 0);
+}
+
+#[tokio::test]
+async fn uploads_below_threshold_are_approved_automatically() {
+    let backend = MemoryBackend::default();
+    let server = Server::with_backend(backend.clone());
+    let mut uploads = Uploads::default();
+    uploads.auto_approve_below = Some(1.0);
+    let file = create_file(&unique_filename("cheap"), b"cheap file");
+
+    let index = uploads.start(&server, &file);
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_file(file).unwrap();
+
+    assert_eq!(uploads.statuses[index].success, Some(true));
+    assert_eq!(backend.wallet_payments(), 1);
+}
+
+#[tokio::test]
+async fn wallet_balance_decreases_after_paying() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let before = server.balance().await.unwrap();
+    let file = create_file(&unique_filename("paid"), b"paid file");
+
+    server.put_file(&file, "paid").await.unwrap();
+    std::fs::remove_file(file).unwrap();
+
+    let after = server.balance().await.unwrap();
+    assert_eq!(after.address, before.address);
+    assert!(after.tokens < before.tokens);
+}
+
+#[tokio::test]
+async fn download_reports_invalid_and_missing_addresses() {
+    let server = Server::with_backend(MemoryBackend::default());
+
+    let invalid = server.get_data("not an address").await.unwrap_err();
+    assert!(invalid.starts_with("Invalid address"));
+
+    let missing = server.get_data(&"ab".repeat(32)).await.unwrap_err();
+    assert!(missing.starts_with("Error downloading data"));
+}
+
+#[tokio::test]
+async fn folder_upload_publishes_archive_after_its_files() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let mut uploads = auto_approving_uploads();
+    let dir = create_folder("folder");
+    let folder = dir.file_name().unwrap().to_string_lossy().to_string();
+
+    let archive = uploads.start_folder(&server, &dir).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let files: Vec<_> = uploads.statuses.iter().filter(|s| !s.is_archive).collect();
+    assert_eq!(files.len(), 2);
+    assert_eq!(files[0].filename, format!("{folder}/a.txt"));
+    assert!(files.iter().all(|s| s.success == Some(true)));
+
+    // the files are shown first, followed by the archive listing them
+    assert_eq!(uploads.display_order(), [1, 2, archive]);
+    let archive = &uploads.statuses[archive];
+    assert!(archive.is_archive);
+    assert!(files.iter().all(|s| s.group == archive.group));
+    let group = archive.group.expect("archive group");
+    assert_eq!(uploads.group_name(group), Some(folder.as_str()));
+    let address = archive.address().expect("archive address");
+    assert!(server.get_data(address).await.is_ok());
+}
+
+#[tokio::test]
+async fn folder_archive_is_not_published_when_a_file_fails() {
+    let backend = MemoryBackend::default();
+    let server = without_retries(backend.clone());
+    let mut uploads = auto_approving_uploads();
+    let dir = create_folder("failing");
+    let folder = dir.file_name().unwrap().to_string_lossy().to_string();
+
+    backend.fail_next_puts(1);
+    let archive = uploads.start_folder(&server, &dir).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+    remove_cached_payments(&sha256::digest(format!("{folder}/a.txt")));
+
+    assert_eq!(uploads.statuses[1].success, Some(false));
+    assert_eq!(uploads.statuses[2].success, Some(true));
+    assert_eq!(uploads.statuses[archive].success, Some(false));
+    assert!(uploads.statuses[archive].message.contains("1 files failed"));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn folder_symlinked_to_itself_is_uploaded_once() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let mut uploads = auto_approving_uploads();
+    let dir = create_folder("looping");
+    std::os::unix::fs::symlink(&dir, dir.join("nested").join("loop")).unwrap();
+
+    let archive = uploads.start_folder(&server, &dir).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(uploads.statuses.len(), 3);
+    assert_eq!(uploads.statuses[archive].success, Some(true));
+}
+
+#[tokio::test]
+async fn empty_folder_fails_its_archive_row() {
+    let server = Server::with_backend(MemoryBackend::default());
+    let mut uploads = auto_approving_uploads();
+    let dir = std::env::temp_dir().join(unique_filename("empty"));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let archive = uploads.start_folder(&server, &dir).unwrap();
+    wait_for_uploads(&mut uploads).await;
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(uploads.statuses.len(), 1);
+    assert_eq!(uploads.statuses[archive].success, Some(false));
+    assert!(uploads.statuses[archive].message.contains("no files"));
+}